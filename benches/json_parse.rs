@@ -0,0 +1,91 @@
+//! Times the `parse` phase of Binance's `exchangeInfo` responses at the size
+//! of a full listing, for comparing the JSON parsers:
+//!
+//! ```text
+//! cargo bench --bench json_parse
+//! cargo bench --bench json_parse --features simd-json
+//! ```
+//!
+//! The recorded responses in `tests/fixtures/recorded` are grown to the
+//! number of symbols Binance lists (each symbol repeated under new names),
+//! replayed through the binary with `--offline --timing-report`, and the
+//! parse times read back from `run_timings`.
+
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+
+use serde_json::Value;
+
+// About the size of Binance's full spot and USDⓈ-M futures listings
+const FILES: &[(&str, usize)] =
+    &[("api_binance_com_api_v3_exchangeInfo.json", 3000), ("fapi_binance_com_fapi_v1_exchangeInfo.json", 600)];
+const RUNS: usize = 10;
+
+// `symbols` padded to `count` entries by repeating the recorded ones renamed
+fn grow(response: &str, count: usize) -> String {
+    let mut response: Value = serde_json::from_str(response).unwrap();
+    let recorded = response["symbols"].as_array().unwrap().clone();
+    let symbols = (0..count)
+        .map(|i| {
+            let mut symbol = recorded[i % recorded.len()].clone();
+            let name = format!("{}{}", symbol["symbol"].as_str().unwrap(), i);
+            symbol["symbol"] = Value::String(name);
+            symbol
+        })
+        .collect();
+    response["symbols"] = Value::Array(symbols);
+    serde_json::to_string(&response).unwrap()
+}
+
+fn main() {
+    let recorded = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/recorded");
+    let dir = std::env::temp_dir().join(format!("crypto_refdata_bench_{}", std::process::id()));
+    let offline = dir.join("offline");
+    std::fs::create_dir_all(&offline).unwrap();
+    let mut bytes = 0;
+    for entry in std::fs::read_dir(&recorded).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        let mut body = std::fs::read_to_string(&path).unwrap();
+        if let Some(&(_, count)) = FILES.iter().find(|(file, _)| *file == name) {
+            body = grow(&body, count);
+            bytes += body.len();
+        }
+        std::fs::write(offline.join(name), body).unwrap();
+    }
+
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        let status = Command::new(env!("CARGO_BIN_EXE_crypto_refdata"))
+            .current_dir(&dir)
+            .arg("--offline")
+            .arg(&offline)
+            .arg("--timing-report")
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "offline refresh failed");
+    }
+
+    let conn = rusqlite::Connection::open(dir.join("crypto_refdata.db")).unwrap();
+    let mut parse_ms: Vec<f64> = conn
+        .prepare("SELECT duration_ms FROM run_timings WHERE exchange = 'binance' AND phase = 'parse'")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    parse_ms.sort_by(f64::total_cmp);
+    let parser = if cfg!(feature = "simd-json") { "simd-json" } else { "serde_json" };
+    println!(
+        "{}: parsed {:.1} MB of Binance exchangeInfo in {:.1} ms (median of {} runs, fastest {:.1} ms; {:.1} s total)",
+        parser,
+        bytes as f64 / 1e6,
+        parse_ms[parse_ms.len() / 2],
+        parse_ms.len(),
+        parse_ms[0],
+        started.elapsed().as_secs_f64(),
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde_json::json;
+
+use crate::changes::ChangeSet;
+use crate::cli::WebhookFormat;
+use crate::config::StaleConfig;
+use crate::db;
+use crate::exchanges::Exchange;
+
+/// POSTs `payload` as JSON to a webhook. The URL is the webhook's credential,
+/// so errors only name its host.
+pub async fn post_webhook(client: &reqwest::Client, url: &str, payload: &serde_json::Value) -> Result<()> {
+    let host = reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+    client
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        .json(payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(reqwest::Error::without_url)
+        .with_context(|| format!("Posting to webhook on {} failed", host.as_deref().unwrap_or("an invalid URL")))?;
+    Ok(())
+}
+
+/// An exchange that hasn't produced data for longer than allowed.
+pub struct Stale {
+    pub exchange: &'static str,
+    pub last_success_at: Option<DateTime<Utc>>,
+}
+
+/// Exchanges whose watermark is older than their allowed age: the per-exchange
+/// override from the config, or `default_max_age`.
+pub fn stale_exchanges(
+    conn: &Connection,
+    exchanges: &[Exchange],
+    default_max_age: Duration,
+    config: &StaleConfig,
+    now: DateTime<Utc>,
+) -> Result<Vec<Stale>> {
+    let mut stale = Vec::new();
+    for &exchange in exchanges {
+        let max_age = config.max_age.get(exchange.name()).map_or(default_max_age, |age| **age);
+        let last_success_at = db::watermark(conn, exchange.name())?;
+        let fresh = last_success_at
+            .is_some_and(|last| now.signed_duration_since(last).to_std().unwrap_or_default() <= max_age);
+        if !fresh {
+            stale.push(Stale { exchange: exchange.name(), last_success_at });
+        }
+    }
+    Ok(stale)
+}
+
+/// Reports stale exchanges, posting a Slack-compatible summary to the
+/// configured webhook. A webhook failure is only a warning.
+pub async fn report_stale(client: &reqwest::Client, config: &StaleConfig, stale: &[Stale]) {
+    let lines: Vec<String> = stale
+        .iter()
+        .map(|entry| match entry.last_success_at {
+            Some(last) => format!("{}: no data since {}", entry.exchange, last),
+            None => format!("{}: never produced data", entry.exchange),
+        })
+        .collect();
+    for line in &lines {
+        eprintln!("Stale: {}", line);
+    }
+
+    let Some(url) = &config.webhook_url else {
+        return;
+    };
+    let payload = json!({
+        "text": format!("Stale reference data feeds:\n{}", lines.join("\n")),
+        "stale": stale
+            .iter()
+            .map(|entry| json!({ "exchange": entry.exchange, "last_success_at": entry.last_success_at }))
+            .collect::<Vec<_>>(),
+    });
+    if let Err(err) = post_webhook(client, url, &payload).await {
+        eprintln!("Warning: {:#}", err);
+    }
+}
+
+/// Posts a run's notable changes to the `--webhook-url` when there are at
+/// least `min_changes` of them. A webhook failure is only a warning.
+pub async fn notify_changes(
+    client: &reqwest::Client,
+    url: &str,
+    format: WebhookFormat,
+    min_changes: usize,
+    changes: &ChangeSet,
+) {
+    if changes.len() == 0 || changes.len() < min_changes {
+        return;
+    }
+    let payload = match format {
+        WebhookFormat::Json => json!(changes),
+        WebhookFormat::Slack => json!({
+            "text": format!("Reference data changes:\n{}", changes.lines().join("\n")),
+        }),
+    };
+    if let Err(err) = post_webhook(client, url, &payload).await {
+        eprintln!("Warning: {:#}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn webhook_errors_name_only_the_host() {
+        // Nothing listens on the discard port
+        let url = "http://127.0.0.1:9/services/T000/B000/secret";
+        let err = post_webhook(&reqwest::Client::new(), url, &json!({})).await.unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.starts_with("Posting to webhook on 127.0.0.1 failed"), "{}", message);
+        assert!(!message.contains("secret"), "{}", message);
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::display;
+use crate::model::ReferenceData;
+use crate::render::Comparison;
+
+/// Identifies an instrument across runs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct InstrumentKey {
+    pub exchange: String,
+    pub product_type: String,
+    pub symbol: String,
+}
+
+impl InstrumentKey {
+    pub fn of(item: &ReferenceData) -> Self {
+        Self {
+            exchange: item.exchange.clone(),
+            product_type: item.product_type.clone(),
+            symbol: item.symbol.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for InstrumentKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.exchange, self.product_type, self.symbol)
+    }
+}
+
+/// The stored sizes of an instrument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Sizes {
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SizeChange {
+    #[serde(flatten)]
+    pub key: InstrumentKey,
+    // For the display scale of the tick and lot columns
+    #[serde(skip)]
+    pub quote: String,
+    pub old: Sizes,
+    pub new: Sizes,
+}
+
+/// Changes that affect how orders must be formed.
+#[derive(Debug, Default, Serialize)]
+pub struct ChangeSet {
+    pub listed: Vec<InstrumentKey>,
+    /// Stored instruments of a fetched exchange that it no longer returned
+    pub delisted: Vec<InstrumentKey>,
+    pub size_changes: Vec<SizeChange>,
+}
+
+impl ChangeSet {
+    pub fn len(&self) -> usize {
+        self.listed.len() + self.delisted.len() + self.size_changes.len()
+    }
+
+    /// One line per change, for logs and chat messages.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        lines.extend(self.listed.iter().map(|key| format!("listed: {}", key)));
+        lines.extend(self.delisted.iter().map(|key| format!("delisted: {}", key)));
+        for change in &self.size_changes {
+            lines.push(format!(
+                "changed: {} tick {} -> {}, lot {} -> {}",
+                change.key, change.old.tick_size, change.new.tick_size, change.old.lot_size, change.new.lot_size
+            ));
+        }
+        lines
+    }
+}
+
+impl Comparison for ChangeSet {
+    fn headers(&self) -> &'static [&'static str] {
+        &["change", "exchange", "product_type", "symbol", "tick_size", "lot_size"]
+    }
+
+    fn rows(&self) -> Vec<Vec<String>> {
+        let row = |change: &str, key: &InstrumentKey, tick_size: String, lot_size: String| {
+            vec![
+                change.to_string(),
+                key.exchange.clone(),
+                key.product_type.clone(),
+                key.symbol.clone(),
+                tick_size,
+                lot_size,
+            ]
+        };
+        let mut rows = Vec::new();
+        rows.extend(self.listed.iter().map(|key| row("listed", key, String::new(), String::new())));
+        rows.extend(self.delisted.iter().map(|key| row("delisted", key, String::new(), String::new())));
+        for change in &self.size_changes {
+            let sizes = |old: Decimal, new: Decimal| {
+                if old == new {
+                    display::size(new, &change.quote)
+                } else {
+                    format!("{} -> {}", display::size(old, &change.quote), display::size(new, &change.quote))
+                }
+            };
+            rows.push(row(
+                "changed",
+                &change.key,
+                sizes(change.old.tick_size, change.new.tick_size),
+                sizes(change.old.lot_size, change.new.lot_size),
+            ));
+        }
+        rows
+    }
+}
+
+/// Compares freshly fetched rows with the `stored` sizes of the exchanges in
+/// `fetched_exchanges`, which are the ones that were fetched successfully.
+pub fn diff(
+    stored: &HashMap<InstrumentKey, Sizes>,
+    data: &[ReferenceData],
+    fetched_exchanges: &[&str],
+) -> ChangeSet {
+    let mut changes = ChangeSet::default();
+    let mut seen = HashSet::new();
+    for item in data {
+        let key = InstrumentKey::of(item);
+        let new = Sizes { tick_size: item.tick_size, lot_size: item.lot_size };
+        match stored.get(&key) {
+            None => changes.listed.push(key.clone()),
+            Some(&old) if old != new => changes.size_changes.push(SizeChange {
+                key: key.clone(),
+                quote: item.quote.clone(),
+                old,
+                new,
+            }),
+            Some(_) => {}
+        }
+        seen.insert(key);
+    }
+
+    changes.delisted = stored
+        .keys()
+        .filter(|key| fetched_exchanges.contains(&key.exchange.as_str()) && !seen.contains(*key))
+        .cloned()
+        .collect();
+    changes.delisted.sort_by_key(|key| key.to_string());
+    changes
+}
@@ -0,0 +1,434 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use crate::config::redact;
+use crate::dns;
+use crate::model::{ProductType, SymbolFormat};
+use crate::output::OutputFormat;
+use crate::render::CompareFormat;
+
+// Serialized for --dump-config
+#[derive(Debug, Parser, Serialize)]
+#[command(about = "Collects instrument reference data from crypto exchanges into SQLite")]
+pub struct Cli {
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+
+    /// TOML config file, see README for the available settings
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Fetch the config over HTTP instead, falling back to the last downloaded
+    /// copy and then to --config when that fails
+    #[arg(long)]
+    #[serde(serialize_with = "redact")]
+    pub config_url: Option<String>,
+
+    /// Where the last config downloaded from --config-url is kept
+    #[arg(long, default_value = "crypto_refdata.config.toml")]
+    pub config_cache: PathBuf,
+
+    /// Print the effective settings (config file with defaults filled in, and
+    /// these options) as TOML and exit; secrets are redacted
+    #[arg(long)]
+    pub dump_config: bool,
+
+    /// How many exchanges are fetched at the same time
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    pub exchange_concurrency: u32,
+
+    /// How many requests a single exchange may have in flight at the same time
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    pub symbol_concurrency: u32,
+
+    /// How long a single request may take before it is abandoned
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    #[serde(with = "humantime_serde")]
+    pub request_timeout: Duration,
+
+    /// How long one symbol's call of a per-symbol enrichment may take before
+    /// that symbol is skipped
+    #[arg(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    #[serde(with = "humantime_serde")]
+    pub symbol_timeout: Duration,
+
+    /// Retry a request this many times, with exponential backoff from 500ms,
+    /// when resolving the host or connecting to it fails
+    #[arg(long, default_value_t = 3)]
+    pub connect_retries: u32,
+
+    /// Send the exchange requests through this proxy, e.g. http://host:3128,
+    /// for exchanges that block the local region
+    #[arg(long, value_parser = parse_proxy)]
+    #[serde(serialize_with = "redact")]
+    pub proxy: Option<reqwest::Proxy>,
+
+    /// Check that every tick (and spot lot) size is positive and no lot size is
+    /// implausibly large
+    #[arg(long)]
+    pub validate_tick_lot_relationship: bool,
+
+    /// Check that every minimum order quantity is a multiple of the lot size
+    #[arg(long)]
+    pub validate_min_qty: bool,
+
+    /// Check that no tick or lot size has more decimals than the price and
+    /// quantity precision the exchange reports, where it reports one
+    #[arg(long)]
+    pub validate_precision: bool,
+
+    /// Fail the run instead of warning when a validation check finds problems
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Refuse to save when an exchange returns more rows than this, e.g. a
+    /// filter that was meant to narrow the universe but didn't
+    #[arg(long, default_value_t = 50_000)]
+    pub max_rows_per_exchange: usize,
+
+    /// Save even when --max-rows-per-exchange is exceeded
+    #[arg(long)]
+    pub force: bool,
+
+    /// Where fetched rows go, repeat to feed several sinks from one fetch
+    #[arg(long, value_enum, default_values_t = [OutputFormat::Sqlite])]
+    pub output: Vec<OutputFormat>,
+
+    /// File for the non-SQLite output, stdout when not given
+    #[arg(long)]
+    pub out_file: Option<PathBuf>,
+
+    /// Only write rows of these product types, e.g. spot or perp,future, to the
+    /// file outputs and `export`; SQLite always stores every row
+    #[arg(long, global = true, value_enum, value_delimiter = ',')]
+    pub product_filter: Vec<ProductType>,
+
+    /// Write a JSON summary of every refresh here: per-exchange counts and
+    /// statuses, duration, config hash and outputs
+    #[arg(long)]
+    pub manifest: Option<PathBuf>,
+
+    /// Compare the per-exchange row counts with this earlier manifest, e.g.
+    /// the previous CI run's, failing on drops beyond --max-count-drop
+    #[arg(long)]
+    pub compare_manifest: Option<PathBuf>,
+
+    /// Largest drop in an exchange's row count, in percent, that
+    /// --compare-manifest only warns about
+    #[arg(long, default_value_t = 20.0, requires = "compare_manifest")]
+    pub max_count_drop: f64,
+
+    /// Commit every N rows instead of saving the whole run in one transaction
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub insert_batch_size: Option<u32>,
+
+    /// Serve the stored data over HTTP on this address, e.g. 127.0.0.1:8080
+    #[arg(long)]
+    pub serve: Option<SocketAddr>,
+
+    /// Keep running and refresh every --interval
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Time between refreshes in daemon mode, e.g. 30m or 1h
+    #[arg(long, default_value = "1h", value_parser = humantime::parse_duration)]
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+
+    /// In daemon mode, prune history and VACUUM the database this often, e.g. 24h
+    #[arg(long, value_parser = humantime::parse_duration)]
+    #[serde(with = "humantime_serde")]
+    pub compact_interval: Option<Duration>,
+
+    /// History older than this is pruned on compaction, e.g. 90d. The state as
+    /// of the cutoff is kept, so as-of queries within the retention still work
+    #[arg(long, value_parser = humantime::parse_duration, requires = "compact_interval")]
+    #[serde(with = "humantime_serde")]
+    pub history_retention: Option<Duration>,
+
+    /// Check that every enabled exchange is reachable and log its clock offset,
+    /// without fetching or saving any instruments
+    #[arg(long)]
+    pub self_check: bool,
+
+    /// Clock offset to an exchange above which --self-check warns
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    #[serde(with = "humantime_serde")]
+    pub max_clock_skew: Duration,
+
+    /// Fail (and post to the configured webhook) when an enabled exchange's
+    /// last successful fetch is older than this, e.g. 24h
+    #[arg(long, value_parser = humantime::parse_duration)]
+    #[serde(with = "humantime_serde")]
+    pub alert_stale: Option<Duration>,
+
+    /// Deprecated: fetch sequentially with a client per exchange and save the
+    /// whole run in one transaction, as before the concurrent pipeline
+    #[arg(long)]
+    pub legacy: bool,
+
+    /// Collect default maker/taker fees where exchanges publish them
+    #[arg(long)]
+    pub with_fees: bool,
+
+    /// Collect the maximum leverage of derivatives where exchanges publish it
+    #[arg(long)]
+    pub with_leverage: bool,
+
+    /// How the symbol column is rendered; base, quote and product_type are
+    /// stored either way. Changing it re-keys every instrument, pick one per database
+    #[arg(long, value_enum, default_value_t = SymbolFormat::Slash)]
+    pub symbol_format: SymbolFormat,
+
+    /// TOML file with desk-mandated tick/lot sizes replacing the exchange's
+    #[arg(long)]
+    pub overrides: Option<PathBuf>,
+
+    /// Kafka bootstrap servers to publish changed rows to
+    #[cfg(feature = "kafka")]
+    #[arg(long, requires = "kafka_topic")]
+    pub kafka_brokers: Option<String>,
+
+    /// Topic for --kafka-brokers, one message per changed row keyed by canonical symbol
+    #[cfg(feature = "kafka")]
+    #[arg(long, requires = "kafka_brokers")]
+    pub kafka_topic: Option<String>,
+
+    /// Format of the RUST_LOG diagnostics on stderr
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Show per-exchange progress bars (only when stderr is a terminal)
+    #[arg(long)]
+    pub progress: bool,
+
+    /// Record per-exchange fetch, parse and save durations in run_timings
+    #[arg(long)]
+    pub timing_report: bool,
+
+    /// How change reports are printed
+    #[arg(long, value_enum, default_value_t = CompareFormat::Table)]
+    pub compare_format: CompareFormat,
+
+    /// Line up the decimal points of tick and lot sizes in table output
+    #[arg(long)]
+    pub align: bool,
+
+    /// POST a summary of listings, delistings and tick/lot changes here
+    #[arg(long)]
+    #[serde(serialize_with = "redact")]
+    pub webhook_url: Option<String>,
+
+    /// Payload format for --webhook-url
+    #[arg(long, value_enum, default_value_t = WebhookFormat::Json)]
+    pub webhook_format: WebhookFormat,
+
+    /// Only notify when a run has at least this many changes
+    #[arg(long, default_value_t = 1)]
+    pub webhook_min_changes: usize,
+
+    /// Refresh a single pair, e.g. BTC/USDT, using targeted endpoints where
+    /// the exchange has them. Delistings aren't detected in this mode
+    #[arg(long, value_parser = parse_pair)]
+    pub refresh_one: Option<Pair>,
+
+    /// Collect these concatenated symbols, e.g. BTCUSDT,ETHUSDC, instead of the
+    /// configured universe
+    #[arg(long, value_delimiter = ',', value_parser = uppercase, conflicts_with = "refresh_one")]
+    pub symbols: Vec<String>,
+
+    /// Use the pairs currently listed in the database as the universe instead
+    /// of the configured symbols
+    #[arg(long, conflicts_with_all = ["refresh_one", "symbols"])]
+    pub symbols_from_db: bool,
+
+    /// Fail, without saving anything, when an entry of the universe (symbol or
+    /// --bases asset) wasn't returned by any exchange
+    #[arg(long)]
+    pub strict_symbols: bool,
+
+    /// Collect every quote leg of these base assets, e.g. BTC,ETH, instead of
+    /// listing each symbol
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = uppercase,
+        conflicts_with_all = ["refresh_one", "symbols", "symbols_from_db"]
+    )]
+    pub bases: Vec<String>,
+
+    /// Use each exchange's N spot pairs with the highest 24h quote volume (and
+    /// their perps) as the universe, instead of listing symbols
+    #[arg(long, conflicts_with_all = ["refresh_one", "symbols", "symbols_from_db", "bases"])]
+    pub top_by_volume: Option<usize>,
+
+    /// Only collect pairs quoted in these assets, e.g. USDT,USDC
+    #[arg(long, value_delimiter = ',', value_parser = uppercase)]
+    pub quote_ccy: Vec<String>,
+
+    /// Skip instruments the exchange reports as not trading (halted, pre-listing,
+    /// settling), so they are marked delisted
+    #[arg(long)]
+    pub only_trading: bool,
+
+    /// Only collect Binance spot symbols carrying this permission, e.g. MARGIN
+    #[arg(long, value_parser = uppercase)]
+    pub require_permission: Option<String>,
+
+    /// Save every exchange response body to this directory, for --offline
+    #[arg(long)]
+    pub save_raw: Option<PathBuf>,
+
+    /// Read exchange responses from a --save-raw directory instead of the network
+    #[arg(long, conflicts_with = "save_raw")]
+    pub offline: Option<PathBuf>,
+
+    /// Refresh twice from --offline or --replay-rows fixtures into a scratch
+    /// database and fail if the second run changes any row
+    #[arg(long, conflicts_with_all = ["daemon", "serve"])]
+    pub verify_idempotent: bool,
+
+    /// Program filtering or enriching the fetched rows before they are stored:
+    /// JSON rows on its stdin, the transformed rows on its stdout
+    #[arg(long)]
+    pub post_process: Option<PathBuf>,
+
+    /// Save every exchange's normalized rows to this directory, for --replay-rows
+    #[arg(long)]
+    pub save_rows: Option<PathBuf>,
+
+    /// Read every exchange's rows from a --save-rows directory instead of fetching
+    #[arg(long, conflicts_with_all = ["save_rows", "offline"])]
+    pub replay_rows: Option<PathBuf>,
+
+    /// Re-fetch only the exchanges that failed in their last logged run
+    #[arg(long)]
+    pub retry_failed: bool,
+
+    /// Refresh only this many exchanges per run, those fetched longest ago
+    /// first, so that consecutive runs take turns
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "retry_failed")]
+    pub limit_exchanges: Option<u32>,
+
+    /// Push run metrics to this Prometheus Pushgateway when the run ends
+    #[arg(long)]
+    #[serde(serialize_with = "redact")]
+    pub pushgateway_url: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print history rows added after a given id as JSON, with the highest id
+    /// seen so the consumer can resume from it
+    Export {
+        /// Only rows with an id greater than this
+        #[arg(long, default_value_t = 0)]
+        since_id: i64,
+    },
+    /// Print the current rows as JSON, optionally only those whose canonical
+    /// symbol carries a tag from the config's [tags]
+    Query {
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Bring the database schema up to date (every run does this too)
+    Migrate {
+        /// Only list the pending migrations, failing if there are any
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the listed venue with the finest tick for a canonical symbol,
+    /// e.g. BTC/USDT-SPOT
+    BestTick {
+        canonical: String,
+    },
+    /// Record that an exchange renamed a native symbol, so the history of the
+    /// old and new names is served as one
+    AddAlias {
+        /// Exchange that renamed the symbol, e.g. binance
+        #[arg(long)]
+        exchange: String,
+        /// Native symbol before the rename, e.g. MATICUSDT
+        old: String,
+        /// Native symbol after the rename, e.g. POLUSDT
+        new: String,
+    },
+}
+
+impl Cli {
+    /// The client for exchange requests, through `--proxy` when given.
+    pub fn http_client(&self) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder().dns_resolver(Arc::new(dns::Resolver));
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        // Only fails when the TLS backend can't be initialized, like Client::new
+        builder.build().expect("Failed to build the HTTP client")
+    }
+
+    /// Whether `--product-filter` keeps rows of `product_type`.
+    pub fn wants_product(&self, product_type: &str) -> bool {
+        self.product_filter.is_empty() || self.product_filter.iter().any(|filter| filter.name() == product_type)
+    }
+}
+
+/// A base and quote asset, e.g. `BTC/USDT`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Pair {
+    pub base: String,
+    pub quote: String,
+}
+
+impl Pair {
+    /// The universe spelling, e.g. `BTCUSDT`.
+    pub fn concat(&self) -> String {
+        format!("{}{}", self.base, self.quote)
+    }
+}
+
+fn parse_pair(value: &str) -> Result<Pair, String> {
+    match value.split_once(['/', '-']) {
+        Some((base, quote)) if !base.is_empty() && !quote.is_empty() => Ok(Pair {
+            base: base.to_uppercase(),
+            quote: quote.to_uppercase(),
+        }),
+        _ => Err(format!("expected BASE/QUOTE, e.g. BTC/USDT, got '{}'", value)),
+    }
+}
+
+fn parse_proxy(value: &str) -> Result<reqwest::Proxy, String> {
+    reqwest::Proxy::all(value).map_err(|err| format!("invalid proxy URL '{}': {}", value, err))
+}
+
+// Assets, symbols and permissions are upper case on every exchange
+fn uppercase(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err("expected a non-empty value".to_string());
+    }
+    Ok(value.to_uppercase())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per event, with its fields and the exchange span
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookFormat {
+    /// The structured change set
+    Json,
+    /// A Slack incoming-webhook message
+    Slack,
+}
@@ -0,0 +1,1127 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Params, Row};
+use rust_decimal::Decimal;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+use crate::changes::{InstrumentKey, Sizes};
+use crate::model::{parse_size, ReferenceData};
+
+// Each entry upgrades the schema by one version, applied in order
+const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "create reference_data",
+        r"CREATE TABLE IF NOT EXISTS reference_data (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            product_type TEXT NOT NULL,
+            exchange TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            tick_size TEXT NOT NULL,
+            lot_size TEXT NOT NULL,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(product_type, exchange, symbol)
+        )",
+    ),
+    (
+        "add contract_size and min_order_base_qty",
+        r"ALTER TABLE reference_data ADD COLUMN contract_size TEXT;
+          ALTER TABLE reference_data ADD COLUMN min_order_base_qty TEXT;",
+    ),
+    (
+        "add inst_family and uly",
+        r"ALTER TABLE reference_data ADD COLUMN inst_family TEXT;
+          ALTER TABLE reference_data ADD COLUMN uly TEXT;",
+    ),
+    (
+        "create fetch_log",
+        r"CREATE TABLE fetch_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            run_id INTEGER NOT NULL,
+            exchange TEXT NOT NULL,
+            status TEXT NOT NULL,
+            row_count INTEGER NOT NULL,
+            error TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+    ),
+    (
+        "add min_price and max_price",
+        r"ALTER TABLE reference_data ADD COLUMN min_price TEXT;
+          ALTER TABLE reference_data ADD COLUMN max_price TEXT;",
+    ),
+    (
+        "add quote_kind",
+        r"ALTER TABLE reference_data ADD COLUMN quote_kind TEXT;",
+    ),
+    (
+        "create reference_data_history",
+        r"CREATE TABLE reference_data_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            product_type TEXT NOT NULL,
+            exchange TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            tick_size TEXT NOT NULL,
+            lot_size TEXT NOT NULL,
+            contract_size TEXT,
+            min_order_base_qty TEXT,
+            inst_family TEXT,
+            uly TEXT,
+            min_price TEXT,
+            max_price TEXT,
+            quote_kind TEXT,
+            updated_at DATETIME,
+            captured_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX reference_data_history_instrument
+            ON reference_data_history (product_type, exchange, symbol, captured_at);",
+    ),
+    (
+        "create watermark",
+        r"CREATE TABLE watermark (
+            exchange TEXT PRIMARY KEY,
+            last_success_at DATETIME NOT NULL
+        )",
+    ),
+    ("add delisted_at", "ALTER TABLE reference_data ADD COLUMN delisted_at DATETIME"),
+    (
+        "add overridden and exchange-reported sizes",
+        r"ALTER TABLE reference_data ADD COLUMN overridden INTEGER NOT NULL DEFAULT 0;
+          ALTER TABLE reference_data ADD COLUMN exchange_tick_size TEXT;
+          ALTER TABLE reference_data ADD COLUMN exchange_lot_size TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN overridden INTEGER NOT NULL DEFAULT 0;
+          ALTER TABLE reference_data_history ADD COLUMN exchange_tick_size TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN exchange_lot_size TEXT;",
+    ),
+    (
+        "add base, quote and canonical",
+        r"ALTER TABLE reference_data ADD COLUMN base TEXT;
+          ALTER TABLE reference_data ADD COLUMN quote TEXT;
+          ALTER TABLE reference_data ADD COLUMN canonical TEXT;
+          CREATE INDEX reference_data_canonical ON reference_data (canonical);
+          ALTER TABLE reference_data_history ADD COLUMN base TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN quote TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN canonical TEXT;",
+    ),
+    (
+        "create run_timings",
+        r"CREATE TABLE run_timings (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            run_id INTEGER NOT NULL,
+            exchange TEXT,
+            phase TEXT NOT NULL,
+            duration_ms REAL NOT NULL,
+            row_count INTEGER NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX run_timings_exchange ON run_timings (exchange, phase, created_at);",
+    ),
+    (
+        "add maker_fee and taker_fee",
+        r"ALTER TABLE reference_data ADD COLUMN maker_fee TEXT;
+          ALTER TABLE reference_data ADD COLUMN taker_fee TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN maker_fee TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN taker_fee TEXT;",
+    ),
+    (
+        "add price_scale and qty_scale",
+        r"ALTER TABLE reference_data ADD COLUMN price_scale INTEGER;
+          ALTER TABLE reference_data ADD COLUMN qty_scale INTEGER;
+          ALTER TABLE reference_data_history ADD COLUMN price_scale INTEGER;
+          ALTER TABLE reference_data_history ADD COLUMN qty_scale INTEGER;",
+    ),
+    (
+        "add min_qty",
+        r"ALTER TABLE reference_data ADD COLUMN min_qty TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN min_qty TEXT;",
+    ),
+    (
+        "add permissions",
+        r"ALTER TABLE reference_data ADD COLUMN permissions TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN permissions TEXT;",
+    ),
+    (
+        "add api_headers to fetch_log",
+        "ALTER TABLE fetch_log ADD COLUMN api_headers TEXT",
+    ),
+    (
+        "add native_symbol",
+        r"ALTER TABLE reference_data ADD COLUMN native_symbol TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN native_symbol TEXT;",
+    ),
+    (
+        "create aliases",
+        r"CREATE TABLE aliases (
+            exchange TEXT NOT NULL,
+            old_symbol TEXT NOT NULL,
+            new_symbol TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (exchange, old_symbol)
+        )",
+    ),
+    (
+        "add max_leverage",
+        r"ALTER TABLE reference_data ADD COLUMN max_leverage TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN max_leverage TEXT;",
+    ),
+    (
+        "add clock_offset_ms to fetch_log",
+        "ALTER TABLE fetch_log ADD COLUMN clock_offset_ms INTEGER",
+    ),
+    (
+        "add tick_size_raw and lot_size_raw",
+        r"ALTER TABLE reference_data ADD COLUMN tick_size_raw TEXT;
+          ALTER TABLE reference_data ADD COLUMN lot_size_raw TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN tick_size_raw TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN lot_size_raw TEXT;",
+    ),
+    (
+        "create tags",
+        r"CREATE TABLE tags (
+            canonical TEXT NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (canonical, tag)
+        )",
+    ),
+    (
+        "add dns_retries to fetch_log",
+        "ALTER TABLE fetch_log ADD COLUMN dns_retries INTEGER NOT NULL DEFAULT 0",
+    ),
+    (
+        "add status",
+        r"ALTER TABLE reference_data ADD COLUMN status TEXT;
+          ALTER TABLE reference_data_history ADD COLUMN status TEXT;",
+    ),
+];
+
+pub const DB_PATH: &str = "crypto_refdata.db";
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+        [],
+    )?;
+    let current: usize = tx
+        .query_row("SELECT version FROM schema_version", [], |row| row.get(0))
+        .optional()?
+        .unwrap_or(0);
+
+    for (version, (description, sql)) in MIGRATIONS.iter().enumerate().skip(current) {
+        tx.execute_batch(sql)
+            .with_context(|| format!("Migration {} ({}) failed", version + 1, description))?;
+        tx.execute("DELETE FROM schema_version", [])?;
+        tx.execute("INSERT INTO schema_version (version) VALUES (?1)", [version + 1])?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// The migrations `open` would apply, as (version, description), without
+/// creating or changing the database.
+pub fn pending_migrations() -> Result<Vec<(usize, &'static str)>> {
+    let current = if Path::new(DB_PATH).exists() {
+        let conn = open_read_only()?;
+        let tracked: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version')",
+            [],
+            |row| row.get(0),
+        )?;
+        if tracked {
+            conn.query_row("SELECT version FROM schema_version", [], |row| row.get(0))
+                .optional()?
+                .unwrap_or(0)
+        } else {
+            0
+        }
+    } else {
+        0
+    };
+    Ok(MIGRATIONS
+        .iter()
+        .enumerate()
+        .skip(current)
+        .map(|(version, (description, _))| (version + 1, *description))
+        .collect())
+}
+
+// How long a connection waits for another one's lock before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens (or creates) the database and brings its schema up to date.
+pub fn open() -> Result<Connection> {
+    // Create or open the SQLite database file
+    let mut conn = Connection::open(DB_PATH)
+        .context("Failed to open SQLite database")?;
+    // With the write-ahead log readers keep seeing the last committed data
+    // while a refresh writes, instead of waiting for it or failing as busy
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+
+    // Create or upgrade tables
+    migrate(&mut conn)?;
+
+    Ok(conn)
+}
+
+/// A fresh, migrated database that lives only as long as the connection.
+pub fn open_in_memory() -> Result<Connection> {
+    let mut conn = Connection::open_in_memory()?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+/// Opens the existing database read-only, without creating or migrating it,
+/// so that readers such as the server never serve an accidentally created
+/// empty file.
+pub fn open_read_only() -> Result<Connection> {
+    if !Path::new(DB_PATH).exists() {
+        bail!("Database {} not found, run a refresh first to create it", DB_PATH);
+    }
+    let conn = Connection::open_with_flags(DB_PATH, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+        .with_context(|| format!("Failed to open SQLite database {} read-only", DB_PATH))?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
+/// Columns written from `ReferenceData`, in `row_values` order. The first
+/// `KEY_COLUMNS` identify the instrument.
+const DATA_COLUMNS: &[&str] = &[
+    "product_type",
+    "exchange",
+    "symbol",
+    "tick_size",
+    "lot_size",
+    "contract_size",
+    "min_order_base_qty",
+    "inst_family",
+    "uly",
+    "min_price",
+    "max_price",
+    "quote_kind",
+    "overridden",
+    "exchange_tick_size",
+    "exchange_lot_size",
+    "base",
+    "quote",
+    "canonical",
+    "maker_fee",
+    "taker_fee",
+    "price_scale",
+    "qty_scale",
+    "min_qty",
+    "permissions",
+    "native_symbol",
+    "max_leverage",
+    "tick_size_raw",
+    "lot_size_raw",
+    "status",
+];
+const KEY_COLUMNS: usize = 3;
+
+fn row_values(item: &ReferenceData) -> Vec<Value> {
+    fn text(value: impl ToString) -> Value {
+        Value::Text(value.to_string())
+    }
+    fn optional(value: Option<impl ToString>) -> Value {
+        value.map_or(Value::Null, text)
+    }
+    // Decimal places of a size, e.g. 0.001 -> 3
+    fn scale(value: Decimal) -> Value {
+        Value::Integer(value.normalize().scale().into())
+    }
+
+    vec![
+        text(&item.product_type),
+        text(&item.exchange),
+        text(&item.symbol),
+        text(item.tick_size),
+        text(item.lot_size),
+        optional(item.contract_size),
+        optional(item.min_order_base_qty),
+        optional(item.inst_family.as_ref()),
+        optional(item.uly.as_ref()),
+        optional(item.min_price),
+        optional(item.max_price),
+        text(item.quote_kind.as_str()),
+        Value::Integer(item.overridden.into()),
+        optional(item.exchange_tick_size),
+        optional(item.exchange_lot_size),
+        text(&item.base),
+        text(&item.quote),
+        text(&item.canonical),
+        optional(item.maker_fee),
+        optional(item.taker_fee),
+        scale(item.tick_size),
+        scale(item.lot_size),
+        optional(item.min_qty),
+        optional(item.permissions.as_ref()),
+        optional(item.native_symbol.as_ref()),
+        optional(item.max_leverage),
+        optional(item.tick_size_raw.as_ref()),
+        optional(item.lot_size_raw.as_ref()),
+        optional(item.status.as_ref()),
+    ]
+}
+
+/// Upsert that leaves the row (and its `updated_at`) alone when nothing changed.
+/// The last placeholder is the run timestamp.
+fn upsert_sql() -> String {
+    let data_columns = &DATA_COLUMNS[KEY_COLUMNS..];
+    let placeholders: Vec<String> = (1..=DATA_COLUMNS.len() + 1).map(|i| format!("?{}", i)).collect();
+    let assignments: Vec<String> = data_columns
+        .iter()
+        .map(|column| format!("{0} = excluded.{0}", column))
+        .collect();
+    let excluded: Vec<String> = data_columns.iter().map(|column| format!("excluded.{}", column)).collect();
+
+    format!(
+        r"INSERT INTO reference_data ({columns}, updated_at)
+          VALUES ({placeholders})
+          ON CONFLICT(product_type, exchange, symbol)
+          DO UPDATE SET {assignments}, updated_at = excluded.updated_at, delisted_at = NULL
+          WHERE ({current}) IS NOT ({excluded}) OR delisted_at IS NOT NULL
+          RETURNING *",
+        columns = DATA_COLUMNS.join(", "),
+        placeholders = placeholders.join(", "),
+        assignments = assignments.join(", "),
+        current = data_columns.join(", "),
+        excluded = excluded.join(", "),
+    )
+}
+
+/// Copies the current version of one instrument into the history table.
+fn history_sql() -> String {
+    let columns = DATA_COLUMNS.join(", ");
+    format!(
+        r"INSERT INTO reference_data_history ({columns}, updated_at, captured_at)
+          SELECT {columns}, updated_at, ?4 FROM reference_data
+          WHERE product_type = ?1 AND exchange = ?2 AND symbol = ?3",
+        columns = columns,
+    )
+}
+
+/// Upserts `data`, in one transaction unless `batch_size` asks to commit
+/// every that many rows. New and changed rows are also appended to
+/// `reference_data_history`, and returned as stored. Every row written is
+/// stamped with `run_timestamp`, so a run's rows share one `updated_at`.
+///
+/// The run's `delisted` instruments and its `log_entries` (see
+/// `log_fetch_run`, whose run id is returned) are written in the same
+/// transaction as the last rows, so a reader never sees the new rows while
+/// the delisted ones are still live, or the fetch log ahead of the data.
+pub fn save_to_sqlite(
+    conn: &mut Connection,
+    data: Vec<ReferenceData>,
+    batch_size: Option<usize>,
+    run_timestamp: DateTime<Utc>,
+    delisted: &[InstrumentKey],
+    log_entries: &[FetchLogEntry],
+) -> Result<(Vec<JsonValue>, i64)> {
+    let batch_size = batch_size.unwrap_or(data.len()).max(1);
+    let timestamp = format_timestamp(run_timestamp);
+    let upsert_sql = upsert_sql();
+    let history_sql = history_sql();
+
+    let mut changed = Vec::new();
+    let mut batches = data.chunks(batch_size).peekable();
+    loop {
+        let tx = conn.transaction()?;
+        {
+            let mut upsert = tx.prepare_cached(&upsert_sql)?;
+            let mut history = tx.prepare_cached(&history_sql)?;
+
+            // Insert or update data
+            for item in batches.next().unwrap_or_default() {
+                let mut values = row_values(item);
+                values.push(Value::Text(timestamp.clone()));
+                let mut saved = upsert.query(params_from_iter(values))?;
+                let Some(row) = saved.next()? else {
+                    continue;
+                };
+                let saved = row_to_json(row)?;
+                history.execute(params![&item.product_type, &item.exchange, &item.symbol, &timestamp])?;
+                changed.push(saved);
+                println!("Saved: {} {} {}", item.exchange, item.product_type, item.symbol);
+            }
+        }
+        if batches.peek().is_none() {
+            mark_delisted(&tx, delisted, run_timestamp)?;
+            let run_id = log_fetch_run(&tx, log_entries)?;
+            tx.commit()?;
+            return Ok((changed, run_id));
+        }
+        tx.commit()?;
+    }
+}
+
+/// Converts a row into a JSON object keyed by column name.
+fn row_to_json(row: &Row) -> Result<JsonValue> {
+    let mut object = serde_json::Map::new();
+    for (i, column) in row.as_ref().column_names().into_iter().enumerate() {
+        let value = match row.get_ref(i)? {
+            ValueRef::Null => JsonValue::Null,
+            ValueRef::Integer(v) => v.into(),
+            ValueRef::Real(v) => v.into(),
+            ValueRef::Text(v) => String::from_utf8_lossy(v).into(),
+            ValueRef::Blob(_) => continue,
+        };
+        object.insert(column.to_string(), value);
+    }
+    Ok(object.into())
+}
+
+fn query_json(conn: &Connection, sql: &str, params: impl Params) -> Result<Vec<JsonValue>> {
+    let mut results = Vec::new();
+    query_each(conn, sql, params, |row| {
+        results.push(row);
+        Ok(())
+    })?;
+    Ok(results)
+}
+
+// Hands every row to `f` as it is read from the cursor
+fn query_each(
+    conn: &Connection,
+    sql: &str,
+    params: impl Params,
+    mut f: impl FnMut(JsonValue) -> Result<()>,
+) -> Result<()> {
+    let mut stmt = conn.prepare(sql)?;
+    let mut rows = stmt.query(params)?;
+    while let Some(row) = rows.next()? {
+        f(row_to_json(row)?)?;
+    }
+    Ok(())
+}
+
+/// History rows with `id > since_id`, as JSON objects keyed by column, and the
+/// highest id seen (`since_id` itself when there are none).
+pub fn history_since(conn: &Connection, since_id: i64) -> Result<(Vec<JsonValue>, i64)> {
+    let rows = query_json(
+        conn,
+        "SELECT * FROM reference_data_history WHERE id > ?1 ORDER BY id",
+        [since_id],
+    )?;
+    let max_id = rows
+        .iter()
+        .filter_map(|row| row["id"].as_i64())
+        .max()
+        .unwrap_or(since_id);
+    Ok((rows, max_id))
+}
+
+/// Replaces the stored tags with `tags`, tag lists by canonical symbol.
+pub fn set_tags(conn: &mut Connection, tags: &HashMap<String, Vec<String>>) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM tags", [])?;
+    {
+        let mut insert = tx.prepare("INSERT OR IGNORE INTO tags (canonical, tag) VALUES (?1, ?2)")?;
+        for (canonical, tags) in tags {
+            for tag in tags {
+                insert.execute(params![canonical, tag])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Records that `exchange` renamed the native symbol `old` to `new`,
+/// replacing any earlier rename of `old`.
+pub fn add_alias(conn: &Connection, exchange: &str, old: &str, new: &str) -> Result<()> {
+    if old == new {
+        bail!("Alias {} -> {} maps a symbol to itself", old, new);
+    }
+    conn.execute(
+        r"INSERT INTO aliases (exchange, old_symbol, new_symbol) VALUES (?1, ?2, ?3)
+          ON CONFLICT (exchange, old_symbol) DO UPDATE SET new_symbol = excluded.new_symbol",
+        params![exchange, old, new],
+    )?;
+    Ok(())
+}
+
+/// Every history row of one instrument, oldest first, following the
+/// recorded renames of its native symbol in both directions so the versions
+/// stored under earlier (or later) names are included. Binance spells spot and
+/// perp alike, so `product_type` tells them apart.
+pub fn instrument_history(
+    conn: &Connection,
+    exchange: &str,
+    native_symbol: &str,
+    product_type: Option<&str>,
+) -> Result<Vec<JsonValue>> {
+    // UNION drops names already seen, so rename cycles terminate
+    query_json(
+        conn,
+        r"WITH RECURSIVE names (symbol) AS (
+              SELECT ?2
+              UNION
+              SELECT old_symbol FROM aliases JOIN names ON new_symbol = names.symbol WHERE exchange = ?1
+              UNION
+              SELECT new_symbol FROM aliases JOIN names ON old_symbol = names.symbol WHERE exchange = ?1
+          )
+          SELECT * FROM reference_data_history
+          WHERE exchange = ?1 AND native_symbol IN (SELECT symbol FROM names) AND (?3 IS NULL OR product_type = ?3)
+          ORDER BY captured_at, id",
+        params![exchange, native_symbol, product_type],
+    )
+}
+
+/// Tick and lot size of every stored instrument that isn't marked delisted.
+pub fn stored_sizes(conn: &Connection) -> Result<HashMap<InstrumentKey, Sizes>> {
+    let mut stmt = conn.prepare(
+        "SELECT exchange, product_type, symbol, tick_size, lot_size FROM reference_data WHERE delisted_at IS NULL",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut sizes = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let key = InstrumentKey {
+            exchange: row.get(0)?,
+            product_type: row.get(1)?,
+            symbol: row.get(2)?,
+        };
+        let tick_size: String = row.get(3)?;
+        let lot_size: String = row.get(4)?;
+        sizes.insert(key, Sizes { tick_size: parse_size(&tick_size)?, lot_size: parse_size(&lot_size)? });
+    }
+    Ok(sizes)
+}
+
+/// The distinct pairs of the listed instruments, concatenated (`BTCUSDT`)
+/// as the universe spells them.
+pub fn stored_symbols(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT base || quote FROM reference_data
+         WHERE delisted_at IS NULL AND base IS NOT NULL AND quote IS NOT NULL ORDER BY 1",
+    )?;
+    let symbols = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+    Ok(symbols)
+}
+
+/// The number of distinct `updated_at` and `captured_at` values, 1 after a
+/// single run into an empty database.
+pub fn distinct_timestamps(conn: &Connection) -> Result<usize> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(DISTINCT ts) FROM (SELECT updated_at AS ts FROM reference_data
+                                         UNION SELECT captured_at FROM reference_data_history)",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(count as usize)
+}
+
+/// Marks instruments an exchange stopped returning as of `run_timestamp`.
+/// The rows are kept, and cleared again by the upsert if the instrument
+/// comes back.
+fn mark_delisted(conn: &Connection, keys: &[InstrumentKey], run_timestamp: DateTime<Utc>) -> Result<()> {
+    let run_timestamp = format_timestamp(run_timestamp);
+    let mut stmt = conn.prepare(
+        "UPDATE reference_data SET delisted_at = ?4
+         WHERE exchange = ?1 AND product_type = ?2 AND symbol = ?3",
+    )?;
+    for key in keys {
+        stmt.execute(params![key.exchange, key.product_type, key.symbol, run_timestamp])?;
+    }
+    Ok(())
+}
+
+/// What a compaction did.
+pub struct Compaction {
+    pub pruned: usize,
+    pub reclaimed_bytes: i64,
+}
+
+/// Deletes the history captured before `cutoff` and VACUUMs the file. A row
+/// is only deleted when a later one, also before the cutoff, supersedes it,
+/// so as-of queries from the cutoff on still see every instrument. `None`
+/// keeps all history and only VACUUMs.
+pub fn compact(conn: &Connection, cutoff: Option<DateTime<Utc>>) -> Result<Compaction> {
+    let pruned = match cutoff {
+        Some(cutoff) => conn.execute(
+            r"DELETE FROM reference_data_history
+              WHERE captured_at < ?1
+                AND EXISTS (
+                    SELECT 1 FROM reference_data_history AS newer
+                    WHERE newer.product_type = reference_data_history.product_type
+                      AND newer.exchange = reference_data_history.exchange
+                      AND newer.symbol = reference_data_history.symbol
+                      AND newer.id > reference_data_history.id
+                      AND newer.captured_at < ?1
+                )",
+            [cutoff.format("%Y-%m-%d %H:%M:%S").to_string()],
+        )?,
+        None => 0,
+    };
+    let size_before = file_size(conn)?;
+    conn.execute_batch("VACUUM").context("VACUUM failed")?;
+    Ok(Compaction { pruned, reclaimed_bytes: size_before - file_size(conn)? })
+}
+
+fn file_size(conn: &Connection) -> Result<i64> {
+    let size = conn.query_row(
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(size)
+}
+
+/// Optional filters for instrument queries, `None` matches everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct InstrumentFilter {
+    pub exchange: Option<String>,
+    pub product_type: Option<String>,
+    // Only instruments whose canonical symbol carries this tag
+    pub tag: Option<String>,
+    // Reconstruct the data as of this time from the history table
+    #[serde(default, deserialize_with = "deserialize_as_of")]
+    pub as_of: Option<DateTime<Utc>>,
+}
+
+// RFC 3339, or a bare date meaning the end of that day in UTC, so that the
+// day's own captures are included. Captures are stamped to the second.
+fn deserialize_as_of<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    if let Ok(date) = NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+        let end_of_day = NaiveTime::from_hms_opt(23, 59, 59).expect("a valid time");
+        return Ok(Some(date.and_time(end_of_day).and_utc()));
+    }
+    DateTime::parse_from_rfc3339(&value)
+        .map(|time| Some(time.with_timezone(&Utc)))
+        .map_err(|_| de::Error::custom(format!("invalid as_of '{}', expected YYYY-MM-DD or RFC 3339", value)))
+}
+
+/// Reference data rows matching `filter`, the current ones or, with `as_of`,
+/// the latest version of every instrument captured by then. Instruments
+/// delisted by then are left out.
+pub fn instruments(conn: &Connection, filter: &InstrumentFilter) -> Result<Vec<JsonValue>> {
+    let mut results = Vec::new();
+    each_instrument(conn, filter, |row| {
+        results.push(row);
+        Ok(())
+    })?;
+    Ok(results)
+}
+
+// `instruments`, handing every row to `f` as it is read
+fn each_instrument(conn: &Connection, filter: &InstrumentFilter, f: impl FnMut(JsonValue) -> Result<()>) -> Result<()> {
+    let Some(as_of) = filter.as_of else {
+        return query_each(
+            conn,
+            r"SELECT * FROM reference_data
+              WHERE (?1 IS NULL OR exchange = ?1) AND (?2 IS NULL OR product_type = ?2)
+                AND (?3 IS NULL OR canonical IN (SELECT canonical FROM tags WHERE tag = ?3))
+              ORDER BY id",
+            params![&filter.exchange, &filter.product_type, &filter.tag],
+            f,
+        );
+    };
+
+    // captured_at uses SQLite's CURRENT_TIMESTAMP format, which sorts as text
+    query_each(
+        conn,
+        r"SELECT * FROM reference_data_history
+          WHERE id IN (
+              SELECT id FROM (
+                  SELECT id, ROW_NUMBER() OVER (
+                      PARTITION BY product_type, exchange, symbol ORDER BY captured_at DESC, id DESC
+                  ) AS version
+                  FROM reference_data_history
+                  WHERE captured_at <= ?3
+                    AND (?1 IS NULL OR exchange = ?1) AND (?2 IS NULL OR product_type = ?2)
+                    AND (?4 IS NULL OR canonical IN (SELECT canonical FROM tags WHERE tag = ?4))
+              )
+              WHERE version = 1
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM reference_data current
+              WHERE current.product_type = reference_data_history.product_type
+                AND current.exchange = reference_data_history.exchange
+                AND current.symbol = reference_data_history.symbol
+                AND current.delisted_at <= ?3
+          )
+          ORDER BY product_type, exchange, symbol",
+        params![&filter.exchange, &filter.product_type, format_timestamp(as_of), &filter.tag],
+        f,
+    )
+}
+
+/// `instruments` read back into `ReferenceData`, each with the `updated_at`
+/// it was stored with.
+#[cfg(test)]
+pub fn instrument_rows(conn: &Connection, filter: &InstrumentFilter) -> Result<Vec<ReferenceData>> {
+    let mut rows = Vec::new();
+    each_instrument_row(conn, filter, |row| {
+        rows.push(row);
+        Ok(())
+    })?;
+    Ok(rows)
+}
+
+/// `instruments` read back into `ReferenceData`, each with the `updated_at`
+/// it was stored with, and handed to `f` one at a time as they are read from
+/// the cursor, e.g. for the file exports, so the table is never held in memory.
+pub fn each_instrument_row(
+    conn: &Connection,
+    filter: &InstrumentFilter,
+    mut f: impl FnMut(ReferenceData) -> Result<()>,
+) -> Result<()> {
+    each_instrument(conn, filter, |row| f(json_to_reference_data(&row)?))
+}
+
+// The inverse of `row_values`, from a row as `row_to_json` returns it
+fn json_to_reference_data(row: &JsonValue) -> Result<ReferenceData> {
+    let text = |column: &str| row[column].as_str().map(str::to_string);
+    let required = |column: &str| text(column).with_context(|| format!("Stored row without {}: {}", column, row));
+    let decimal = |column: &str| text(column).map(|value| parse_size(&value)).transpose();
+    let item = ReferenceData::new(
+        &required("exchange")?,
+        &required("product_type")?,
+        &required("base")?,
+        &required("quote")?,
+        parse_size(&required("tick_size")?)?,
+        parse_size(&required("lot_size")?)?,
+    );
+    Ok(ReferenceData {
+        symbol: required("symbol")?,
+        native_symbol: text("native_symbol"),
+        canonical: required("canonical")?,
+        tick_size_raw: text("tick_size_raw"),
+        lot_size_raw: text("lot_size_raw"),
+        min_qty: decimal("min_qty")?,
+        contract_size: decimal("contract_size")?,
+        min_order_base_qty: decimal("min_order_base_qty")?,
+        inst_family: text("inst_family"),
+        uly: text("uly"),
+        min_price: decimal("min_price")?,
+        max_price: decimal("max_price")?,
+        overridden: row["overridden"].as_i64() == Some(1),
+        exchange_tick_size: decimal("exchange_tick_size")?,
+        exchange_lot_size: decimal("exchange_lot_size")?,
+        maker_fee: decimal("maker_fee")?,
+        taker_fee: decimal("taker_fee")?,
+        permissions: text("permissions"),
+        max_leverage: decimal("max_leverage")?,
+        status: text("status"),
+        updated_at: text("updated_at").map(|value| parse_timestamp(&value)).transpose()?,
+        ..item
+    })
+}
+
+/// The listed rows of every venue sharing a canonical symbol, e.g. all
+/// `BTC/USDT-SPOT` markets whatever their stablecoin quote.
+pub fn instruments_by_canonical(conn: &Connection, canonical: &str) -> Result<Vec<JsonValue>> {
+    query_json(
+        conn,
+        r"SELECT * FROM reference_data
+          WHERE canonical = ?1 AND delisted_at IS NULL
+          ORDER BY exchange, symbol",
+        [canonical],
+    )
+}
+
+/// A column of a live table, as reported by `PRAGMA table_info`.
+pub struct Column {
+    pub name: String,
+    // Declared type, e.g. TEXT or DATETIME
+    pub decl_type: String,
+    // NOT NULL or the INTEGER PRIMARY KEY
+    pub not_null: bool,
+}
+
+/// The columns `table` currently has, in table order.
+pub fn columns(conn: &Connection, table: &str) -> Result<Vec<Column>> {
+    let mut stmt = conn.prepare("SELECT name, type, \"notnull\", pk FROM pragma_table_info(?1) ORDER BY cid")?;
+    let columns = stmt
+        .query_map([table], |row| {
+            Ok(Column {
+                name: row.get(0)?,
+                decl_type: row.get(1)?,
+                not_null: row.get::<_, bool>(2)? || row.get::<_, i64>(3)? > 0,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(columns)
+}
+
+/// Outcome of fetching one exchange, as recorded in `fetch_log`.
+pub struct FetchLogEntry<'a> {
+    pub exchange: &'a str,
+    pub row_count: usize,
+    pub error: Option<String>,
+    // Rate-limit and version headers as a JSON object, when the exchange sent any
+    pub api_headers: Option<String>,
+    // Requests retried after the host name didn't resolve
+    pub dns_retries: u32,
+    // Symbols skipped after --symbol-timeout, only reported in the manifest
+    pub skipped: Vec<String>,
+}
+
+/// Records the outcome of every exchange fetched in this run under a new run
+/// id, which is returned, and moves the watermark of those that returned data.
+pub fn log_fetch_run(conn: &Connection, entries: &[FetchLogEntry]) -> Result<i64> {
+    let run_id: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(run_id), 0) + 1 FROM fetch_log",
+        [],
+        |row| row.get(0),
+    )?;
+    for entry in entries {
+        let status = if entry.error.is_some() { "error" } else { "ok" };
+        conn.execute(
+            r"INSERT INTO fetch_log (run_id, exchange, status, row_count, error, api_headers, dns_retries)
+              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                run_id,
+                entry.exchange,
+                status,
+                entry.row_count,
+                &entry.error,
+                &entry.api_headers,
+                entry.dns_retries
+            ],
+        )?;
+        if entry.error.is_none() && entry.row_count > 0 {
+            conn.execute(
+                r"INSERT INTO watermark (exchange, last_success_at) VALUES (?1, CURRENT_TIMESTAMP)
+                  ON CONFLICT(exchange) DO UPDATE SET last_success_at = excluded.last_success_at",
+                [entry.exchange],
+            )?;
+        }
+    }
+    Ok(run_id)
+}
+
+/// How long one phase of a run took, for `--timing-report`.
+pub struct RunTiming<'a> {
+    // None for phases covering all exchanges, e.g. the save
+    pub exchange: Option<&'a str>,
+    pub phase: &'static str,
+    pub duration: Duration,
+    pub row_count: usize,
+}
+
+/// Records the phase timings of the run `run_id` from `log_fetch_run`.
+pub fn log_run_timings(conn: &Connection, run_id: i64, timings: &[RunTiming]) -> Result<()> {
+    let mut stmt = conn.prepare(
+        r"INSERT INTO run_timings (run_id, exchange, phase, duration_ms, row_count)
+          VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for timing in timings {
+        let duration_ms = timing.duration.as_secs_f64() * 1000.0;
+        stmt.execute(params![run_id, timing.exchange, timing.phase, duration_ms, timing.row_count])?;
+    }
+    Ok(())
+}
+
+/// When `exchange` last returned a non-empty result without error, if ever.
+pub fn watermark(conn: &Connection, exchange: &str) -> Result<Option<DateTime<Utc>>> {
+    let last: Option<String> = conn
+        .query_row(
+            "SELECT last_success_at FROM watermark WHERE exchange = ?1",
+            [exchange],
+            |row| row.get(0),
+        )
+        .optional()?;
+    last.map(|last| parse_timestamp(&last)).transpose()
+}
+
+/// Formats `timestamp` like SQLite's `CURRENT_TIMESTAMP`, so it sorts and
+/// compares with the timestamps SQLite wrote itself.
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Parses SQLite's `CURRENT_TIMESTAMP` format, which is in UTC.
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .with_context(|| format!("Invalid timestamp '{}'", value))?;
+    Ok(naive.and_utc())
+}
+
+/// Records the clock offsets measured by `--self-check` under a new run id,
+/// as `self_check` entries that the fetch queries skip.
+pub fn log_clock_offsets(conn: &Connection, offsets: &[(&str, i64)]) -> Result<()> {
+    let run_id: i64 = conn.query_row("SELECT COALESCE(MAX(run_id), 0) + 1 FROM fetch_log", [], |row| row.get(0))?;
+    for (exchange, offset_ms) in offsets {
+        conn.execute(
+            r"INSERT INTO fetch_log (run_id, exchange, status, row_count, clock_offset_ms)
+              VALUES (?1, ?2, 'self_check', 0, ?3)",
+            params![run_id, exchange, offset_ms],
+        )?;
+    }
+    Ok(())
+}
+
+/// Exchanges whose most recent fetch (not self-check) entry is a failure.
+pub fn failed_exchanges(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        r"SELECT exchange FROM fetch_log AS f
+          WHERE status = 'error'
+            AND id = (
+                SELECT MAX(id) FROM fetch_log WHERE exchange = f.exchange AND status IN ('ok', 'error')
+            )
+          ORDER BY exchange",
+    )?;
+    let exchanges = stmt
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(exchanges)
+}
+
+/// The `fetch_log` id of every exchange's latest fetch, successful or not;
+/// a higher id is a more recent fetch.
+pub fn last_fetches(conn: &Connection) -> Result<HashMap<String, i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT exchange, MAX(id) FROM fetch_log WHERE status IN ('ok', 'error') GROUP BY exchange",
+    )?;
+    let fetches = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(fetches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spot(quote: &str) -> ReferenceData {
+        ReferenceData::new("binance", "spot", "BTC", quote, Decimal::new(1, 2), Decimal::new(1, 5))
+            .with_native_symbol(&format!("BTC{}", quote))
+    }
+
+    fn as_of(value: &str) -> InstrumentFilter {
+        serde_json::from_value(serde_json::json!({ "as_of": value })).unwrap()
+    }
+
+    #[test]
+    fn readers_never_see_half_a_run() {
+        const ROWS: i64 = 50;
+        const RUNS: i64 = 40;
+        let path = std::env::temp_dir().join(format!("crypto_refdata_readers_{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut conn = Connection::open(&path).unwrap();
+        conn.pragma_update(None, "journal_mode", "WAL").unwrap();
+        migrate(&mut conn).unwrap();
+        let started = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap().to_utc();
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+                conn.busy_timeout(BUSY_TIMEOUT).unwrap();
+                let mut reads = 0;
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    // As the server reads: one snapshot per request
+                    let snapshot = conn.unchecked_transaction().unwrap();
+                    let runs: i64 = snapshot
+                        .query_row("SELECT COUNT(DISTINCT run_id) FROM fetch_log", [], |row| row.get(0))
+                        .unwrap();
+                    let (live, latest): (i64, Option<String>) = snapshot
+                        .query_row(
+                            "SELECT COUNT(*), MAX(updated_at) FROM reference_data WHERE delisted_at IS NULL",
+                            [],
+                            |row| Ok((row.get(0)?, row.get(1)?)),
+                        )
+                        .unwrap();
+                    let stale: i64 = snapshot
+                        .query_row(
+                            "SELECT COUNT(*) FROM reference_data WHERE delisted_at IS NULL AND updated_at != ?1",
+                            [&latest],
+                            |row| row.get(0),
+                        )
+                        .unwrap();
+                    drop(snapshot);
+                    let Some(latest) = latest else {
+                        assert_eq!(runs, 0);
+                        continue;
+                    };
+                    // Run i is stamped i seconds after the first, and delists the last row when odd
+                    let run = (parse_timestamp(&latest).unwrap() - started).num_seconds();
+                    assert_eq!(runs, run + 1, "fetch log out of step with the rows");
+                    assert_eq!(stale, 0, "live rows from an earlier run");
+                    assert_eq!(live, if run % 2 == 1 { ROWS - 1 } else { ROWS });
+                    reads += 1;
+                }
+                reads
+            });
+
+            for run in 0..RUNS {
+                // A new tick every run, so that every row is rewritten
+                let rows: Vec<ReferenceData> = (0..ROWS)
+                    .map(|i| {
+                        let base = format!("C{}", i);
+                        ReferenceData::new("binance", "spot", &base, "USDT", Decimal::new(run + 1, 2), Decimal::ONE)
+                    })
+                    .collect();
+                let mut delisted = Vec::new();
+                let mut rows = rows;
+                if run % 2 == 1 {
+                    delisted.push(InstrumentKey::of(&rows.pop().unwrap()));
+                }
+                let entries = [FetchLogEntry {
+                    exchange: "binance",
+                    row_count: rows.len(),
+                    error: None,
+                    api_headers: None,
+                    dns_retries: 0,
+                    skipped: Vec::new(),
+                }];
+                let timestamp = started + chrono::Duration::seconds(run);
+                save_to_sqlite(&mut conn, rows, None, timestamp, &delisted, &entries).unwrap();
+            }
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+            assert!(reader.join().unwrap() > 0);
+        });
+        drop(conn);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn small_batches_save_every_row() {
+        let mut conn = open_in_memory().unwrap();
+        let rows = ["USDT", "USDC", "FDUSD", "EUR", "TRY"].map(spot).to_vec();
+        let entries = [FetchLogEntry {
+            exchange: "binance",
+            row_count: 5,
+            error: None,
+            api_headers: None,
+            dns_retries: 0,
+            skipped: Vec::new(),
+        }];
+        let (changed, run_id) = save_to_sqlite(&mut conn, rows, Some(2), Utc::now(), &[], &entries).unwrap();
+        assert_eq!((changed.len(), run_id), (5, 1));
+        assert_eq!(instrument_rows(&conn, &InstrumentFilter::default()).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn bare_as_of_date_includes_that_day() {
+        let mut conn = open_in_memory().unwrap();
+        let captured = DateTime::parse_from_rfc3339("2024-01-02T10:00:00Z").unwrap().to_utc();
+        save_to_sqlite(&mut conn, vec![spot("USDT")], None, captured, &[], &[]).unwrap();
+        assert_eq!(instruments(&conn, &as_of("2024-01-01")).unwrap().len(), 0);
+        assert_eq!(instruments(&conn, &as_of("2024-01-02")).unwrap().len(), 1);
+        assert_eq!(instruments(&conn, &as_of("2024-01-02T09:59:59Z")).unwrap().len(), 0);
+        assert_eq!(instruments(&conn, &as_of("2024-01-02T10:00:00Z")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn every_quote_leg_of_a_base_is_stored() {
+        let mut conn = open_in_memory().unwrap();
+        let rows = vec![spot("USDT"), spot("USDC")];
+        let (changed, _) = save_to_sqlite(&mut conn, rows, None, Utc::now(), &[], &[]).unwrap();
+        assert_eq!(changed.len(), 2);
+        let symbols: Vec<String> =
+            instrument_rows(&conn, &InstrumentFilter::default()).unwrap().into_iter().map(|row| row.symbol).collect();
+        assert_eq!(symbols, ["BTC/USDT-SPOT", "BTC/USDC-SPOT"]);
+    }
+}
@@ -0,0 +1,44 @@
+//! Display scales: how many decimals tick and lot sizes show in the change
+//! table and the CSV output, per quote currency. Storage and JSON keep the
+//! exact values.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use rust_decimal::Decimal;
+
+// Cents for dollar-like quotes, satoshi-like precision for the majors
+const DEFAULT_SCALES: &[(&str, u32)] = &[
+    ("USD", 2),
+    ("USDT", 2),
+    ("USDC", 2),
+    ("FDUSD", 2),
+    ("EUR", 2),
+    ("BTC", 8),
+    ("ETH", 8),
+];
+
+static SCALES: OnceLock<HashMap<String, u32>> = OnceLock::new();
+
+/// Sets up the scales once per process: the defaults, overridden or extended
+/// by the config's `[display_scale]` entries.
+pub fn init(configured: &HashMap<String, u32>) {
+    let mut scales: HashMap<String, u32> =
+        DEFAULT_SCALES.iter().map(|&(quote, scale)| (quote.to_string(), scale)).collect();
+    scales.extend(configured.iter().map(|(quote, &scale)| (quote.to_uppercase(), scale)));
+    let _ = SCALES.set(scales);
+}
+
+/// A tick or lot `value` padded with zeros to the display scale of the
+/// instrument's `quote`, e.g. a `0.1` tick on a USDT pair as `0.10`. Values
+/// with more decimals than that are shown in full, never rounded.
+pub fn size(value: Decimal, quote: &str) -> String {
+    let Some(&scale) = SCALES.get().and_then(|scales| scales.get(quote)) else {
+        return value.to_string();
+    };
+    let mut padded = value;
+    if padded.scale() < scale {
+        padded.rescale(scale);
+    }
+    padded.to_string()
+}
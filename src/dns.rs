@@ -0,0 +1,58 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+/// Resolves host names like reqwest's default resolver (the system's
+/// getaddrinfo), but fails with a `DnsError`, so that a failed lookup can be
+/// told apart from a failed connection by type rather than by message.
+#[derive(Debug, Default)]
+pub struct Resolver;
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            // The port is replaced by the URL's
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await
+                .map_err(|source| DnsError { host: host.clone(), source })?
+                .collect();
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// A host name that didn't resolve.
+#[derive(Debug)]
+pub struct DnsError {
+    pub host: String,
+    pub source: io::Error,
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to resolve {}: {}", self.host, self.source)
+    }
+}
+
+impl Error for DnsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Whether `err` or one of its sources is a `DnsError`.
+pub fn is_dns_error(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err.is::<DnsError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
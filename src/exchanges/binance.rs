@@ -0,0 +1,369 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use super::{ApiEnvelope, FetchContext};
+use crate::model::{parse_size, ReferenceData};
+use crate::progress;
+
+// Binance Spot structures
+#[derive(Debug, Deserialize)]
+struct BinanceSpotExchangeInfo {
+    symbols: Vec<BinanceSpotSymbol>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceSpotSymbol {
+    symbol: String,
+    base_asset: String,
+    quote_asset: String,
+    // TRADING, BREAK, HALT, ...
+    #[serde(default)]
+    status: String,
+    // Older responses list permissions flat, newer ones as sets with `permissions` left empty
+    #[serde(default)]
+    permissions: Vec<String>,
+    #[serde(default)]
+    permission_sets: Vec<Vec<String>>,
+    filters: Vec<FilterEntry>,
+}
+
+impl BinanceSpotSymbol {
+    // Every permission of either field, sorted and deduplicated
+    fn permissions(&self) -> BTreeSet<&str> {
+        self.permissions
+            .iter()
+            .chain(self.permission_sets.iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+// Binance reports errors through the HTTP status only
+impl ApiEnvelope for BinanceSpotExchangeInfo {}
+
+// Binance Futures structures
+#[derive(Debug, Deserialize)]
+struct BinanceFuturesExchangeInfo {
+    symbols: Vec<BinanceFuturesSymbol>,
+}
+
+impl ApiEnvelope for BinanceFuturesExchangeInfo {}
+
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+struct BinanceTickers(Vec<BinanceTicker>);
+
+impl ApiEnvelope for BinanceTickers {}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceTicker {
+    symbol: String,
+    quote_volume: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BinanceFuturesSymbol {
+    symbol: String,
+    base_asset: String,
+    quote_asset: String,
+    // PERPETUAL, CURRENT_QUARTER, ...
+    contract_type: String,
+    // TRADING, BREAK, HALT, ...
+    #[serde(default)]
+    status: String,
+    // Decimals of price and quantity, next to the PRICE_FILTER and LOT_SIZE sizes
+    #[serde(default)]
+    price_precision: Option<u32>,
+    #[serde(default)]
+    quantity_precision: Option<u32>,
+    filters: Vec<FilterEntry>,
+}
+
+// Filters we model, falling back to just the type for everything else
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FilterEntry {
+    Known(BinanceFilter),
+    Other {
+        #[serde(rename = "filterType")]
+        filter_type: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "filterType")]
+enum BinanceFilter {
+    #[serde(rename = "PRICE_FILTER", rename_all = "camelCase")]
+    PriceFilter {
+        tick_size: String,
+        // Not every market reports price bounds
+        #[serde(default)]
+        min_price: String,
+        #[serde(default)]
+        max_price: String,
+    },
+    #[serde(rename = "LOT_SIZE", rename_all = "camelCase")]
+    LotSize {
+        step_size: String,
+        #[serde(default)]
+        min_qty: String,
+    },
+}
+
+// Documented filters we don't need. Anything else is new and gets reported.
+const IGNORED_FILTERS: &[&str] = &[
+    "PERCENT_PRICE",
+    "PERCENT_PRICE_BY_SIDE",
+    "MIN_NOTIONAL",
+    "NOTIONAL",
+    "ICEBERG_PARTS",
+    "MARKET_LOT_SIZE",
+    "MAX_NUM_ORDERS",
+    "MAX_NUM_ALGO_ORDERS",
+    "MAX_NUM_ICEBERG_ORDERS",
+    "MAX_NUM_ORDER_AMENDS",
+    "MAX_NUM_ORDER_LISTS",
+    "MAX_POSITION",
+    "TRAILING_DELTA",
+    "POSITION_RISK_CONTROL",
+];
+
+fn note_other_filter(ctx: &FetchContext, symbol: &str, filter_type: &str) {
+    if IGNORED_FILTERS.contains(&filter_type) {
+        return;
+    }
+    tracing::debug!(symbol, filter_type, "Unrecognized Binance filter type");
+    ctx.note_unrecognized(format!("binance filter {}", filter_type));
+}
+
+// Binance reports a disabled bound as 0, a missing one is left empty
+fn parse_bound(value: &str) -> Result<Option<Decimal>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let bound = parse_size(value)?;
+    Ok((!bound.is_zero()).then_some(bound))
+}
+
+fn is_trading(ctx: &FetchContext, status: &str) -> bool {
+    !ctx.only_trading() || status == "TRADING"
+}
+
+pub async fn fetch(ctx: &FetchContext) -> Result<Vec<ReferenceData>> {
+    let (mut results, perps) = tokio::try_join!(fetch_spot(ctx), fetch_futures(ctx))?;
+    results.extend(perps);
+    Ok(results)
+}
+
+async fn fetch_spot(ctx: &FetchContext) -> Result<Vec<ReferenceData>> {
+    progress::println("Processing Binance SPOT...");
+    let url = "https://api.binance.com/api/v3/exchangeInfo";
+    let response: BinanceSpotExchangeInfo = ctx.fetch_json(url).await?;
+
+    let mut results = Vec::new();
+    for symbol_info in response.symbols {
+        if !ctx.wants(&symbol_info.base_asset, &symbol_info.quote_asset) || !is_trading(ctx, &symbol_info.status) {
+            continue;
+        }
+        let permissions = symbol_info.permissions();
+        if ctx.required_permission().is_some_and(|required| !permissions.contains(required)) {
+            continue;
+        }
+        let permissions = permissions.into_iter().collect::<Vec<_>>().join(",");
+        let context = || format!("Parsing binance spot {}", symbol_info.symbol);
+
+        let mut tick_size = String::new();
+        let mut lot_size = String::new();
+        let mut min_price = None;
+        let mut max_price = None;
+        let mut min_qty = None;
+
+        for filter in symbol_info.filters {
+            let filter = match filter {
+                FilterEntry::Known(filter) => filter,
+                FilterEntry::Other { filter_type } => {
+                    note_other_filter(ctx, &symbol_info.symbol, &filter_type);
+                    continue;
+                }
+            };
+            match filter {
+                BinanceFilter::PriceFilter { tick_size: tick, min_price: min, max_price: max } => {
+                    tick_size = tick;
+                    min_price = parse_bound(&min).with_context(context)?;
+                    max_price = parse_bound(&max).with_context(context)?;
+                }
+                BinanceFilter::LotSize { step_size, min_qty: min } => {
+                    lot_size = step_size;
+                    min_qty = parse_bound(&min).with_context(context)?;
+                }
+            }
+        }
+
+        results.push(ReferenceData {
+            min_price,
+            max_price,
+            min_qty,
+            permissions: (!permissions.is_empty()).then_some(permissions),
+            status: Some(symbol_info.status.clone()),
+            ..ReferenceData::new(
+                "binance",
+                "spot",
+                &symbol_info.base_asset,
+                &symbol_info.quote_asset,
+                parse_size(&tick_size).with_context(context)?,
+                parse_size(&lot_size).with_context(context)?,
+            )
+        }
+        .with_raw_sizes(&tick_size, &lot_size)
+        .with_native_symbol(&symbol_info.symbol));
+    }
+
+    Ok(results)
+}
+
+async fn fetch_futures(ctx: &FetchContext) -> Result<Vec<ReferenceData>> {
+    progress::println("Processing Binance PERP...");
+    let url = "https://fapi.binance.com/fapi/v1/exchangeInfo";
+    let response: BinanceFuturesExchangeInfo = ctx.fetch_json(url).await?;
+
+    let mut results = Vec::new();
+    for symbol_info in response.symbols {
+        // Delivery contracts share base and quote with the perp
+        if symbol_info.contract_type != "PERPETUAL" {
+            continue;
+        }
+        if !ctx.wants(&symbol_info.base_asset, &symbol_info.quote_asset) || !is_trading(ctx, &symbol_info.status) {
+            continue;
+        }
+        let context = || format!("Parsing binance perp {}", symbol_info.symbol);
+
+        let mut tick_size = String::new();
+        let mut lot_size = String::new();
+        let mut min_price = None;
+        let mut max_price = None;
+        let mut min_qty = None;
+
+        for filter in symbol_info.filters {
+            let filter = match filter {
+                FilterEntry::Known(filter) => filter,
+                FilterEntry::Other { filter_type } => {
+                    note_other_filter(ctx, &symbol_info.symbol, &filter_type);
+                    continue;
+                }
+            };
+            match filter {
+                BinanceFilter::PriceFilter { tick_size: tick, min_price: min, max_price: max } => {
+                    tick_size = tick;
+                    min_price = parse_bound(&min).with_context(context)?;
+                    max_price = parse_bound(&max).with_context(context)?;
+                }
+                BinanceFilter::LotSize { step_size, min_qty: min } => {
+                    lot_size = step_size;
+                    min_qty = parse_bound(&min).with_context(context)?;
+                }
+            }
+        }
+
+        // USDⓈ-M contracts are quoted directly in base units, there is no
+        // contract size to report.
+        results.push(ReferenceData {
+            min_price,
+            max_price,
+            min_qty,
+            price_precision: symbol_info.price_precision,
+            qty_precision: symbol_info.quantity_precision,
+            status: Some(symbol_info.status.clone()),
+            ..ReferenceData::new(
+                "binance",
+                "perp",
+                &symbol_info.base_asset,
+                &symbol_info.quote_asset,
+                parse_size(&tick_size).with_context(context)?,
+                parse_size(&lot_size).with_context(context)?,
+            )
+        }
+        .with_raw_sizes(&tick_size, &lot_size)
+        .with_native_symbol(&symbol_info.symbol));
+    }
+
+    Ok(results)
+}
+
+/// 24h quote volume of every spot symbol, for --top-by-volume.
+pub async fn spot_volumes(ctx: &FetchContext) -> Result<HashMap<String, Decimal>> {
+    let url = "https://api.binance.com/api/v3/ticker/24hr";
+    let response: BinanceTickers = ctx.fetch_json(url).await?;
+    response
+        .0
+        .into_iter()
+        .map(|ticker| {
+            let volume = parse_size(&ticker.quote_volume)
+                .with_context(|| format!("Parsing the binance volume of {}", ticker.symbol))?;
+            Ok((ticker.symbol, volume))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchanges::{fixture_dir, parse_checked, test_context};
+
+    #[tokio::test]
+    async fn price_filter_fields_are_read() {
+        let ctx = test_context(&["--offline", &fixture_dir("recorded")], &["BTCUSDT"]);
+        let (spot, perp) = tokio::try_join!(fetch_spot(&ctx), fetch_futures(&ctx)).unwrap();
+        assert_eq!(spot.len(), 1);
+        assert_eq!(spot[0].tick_size.to_string(), "0.01");
+        assert_eq!(spot[0].min_price.unwrap().to_string(), "0.01");
+        assert_eq!(spot[0].max_price.unwrap().to_string(), "1000000");
+        assert_eq!(perp.len(), 1);
+        assert_eq!(perp[0].tick_size.to_string(), "0.1");
+        assert_eq!(perp[0].min_price.unwrap().to_string(), "556.8");
+        assert_eq!(perp[0].max_price.unwrap().to_string(), "4529764");
+    }
+
+    #[tokio::test]
+    async fn every_quote_leg_is_collected() {
+        let ctx = test_context(&["--offline", &fixture_dir("recorded")], &["BTCUSDT", "BTCUSDC"]);
+        let spot = fetch_spot(&ctx).await.unwrap();
+        let symbols: Vec<&str> = spot.iter().filter_map(|row| row.native_symbol.as_deref()).collect();
+        assert_eq!(symbols, ["BTCUSDT", "BTCUSDC"]);
+    }
+
+    #[tokio::test]
+    async fn zero_price_bounds_are_absent() {
+        let ctx = test_context(&["--offline", &fixture_dir("recorded")], &["FOOUSDT"]);
+        let spot = fetch_spot(&ctx).await.unwrap();
+        assert_eq!(spot[0].tick_size.to_string(), "0.0001");
+        assert_eq!((spot[0].min_price, spot[0].max_price), (None, None));
+    }
+
+    #[test]
+    fn exchange_info_passes() {
+        let body = r#"{"symbols": [{"symbol": "BTCUSDT", "baseAsset": "BTC", "quoteAsset": "USDT",
+            "contractType": "PERPETUAL", "filters": []}]}"#;
+        let spot: BinanceSpotExchangeInfo = parse_checked(body).unwrap();
+        assert_eq!(spot.symbols[0].symbol, "BTCUSDT");
+        let futures: BinanceFuturesExchangeInfo = parse_checked(body).unwrap();
+        assert_eq!(futures.symbols[0].contract_type, "PERPETUAL");
+        let tickers: BinanceTickers = parse_checked(r#"[{"symbol": "BTCUSDT", "quoteVolume": "1.5"}]"#).unwrap();
+        assert_eq!(tickers.0[0].quote_volume, "1.5");
+    }
+
+    // Binance sends errors with an HTTP error status, which fails the request
+    // before parsing; an error body that got through still isn't mistaken for data
+    #[test]
+    fn error_body_fails() {
+        let body = r#"{"code": -1121, "msg": "Invalid symbol."}"#;
+        assert!(parse_checked::<BinanceSpotExchangeInfo>(body).is_err());
+        assert!(parse_checked::<BinanceFuturesExchangeInfo>(body).is_err());
+        assert!(parse_checked::<BinanceTickers>(body).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{ApiEnvelope, FetchContext};
+use crate::config::{redact, redact_set};
+use crate::model::{parse_size, ReferenceData};
+use crate::progress;
+
+/// An exchange described in the config instead of in code: one REST
+/// endpoint returning a JSON array of instruments, located with JSON
+/// pointers (RFC 6901, e.g. `/data/symbols`). Venues that only list symbol
+/// names get a per-symbol `details` endpoint as well.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenericRestConfig {
+    pub name: String,
+    // URLs may embed credentials, like the other redacted ones
+    #[serde(serialize_with = "redact_set")]
+    pub url: String,
+    #[serde(default = "default_product_type")]
+    pub product_type: String,
+    // Pointer to the instrument array in the response, "" for the root
+    #[serde(default)]
+    pub instruments: String,
+    // Pointers into each instrument
+    pub base: String,
+    pub quote: String,
+    pub tick_size: String,
+    pub lot_size: String,
+    // The venue's own symbol, stored as native_symbol
+    #[serde(default)]
+    pub symbol: Option<String>,
+    // Per-symbol endpoint with a {symbol} placeholder, e.g. Gemini's
+    // /v1/symbols/details/{symbol}. The instrument pointers then point into
+    // its response, and `symbol` into the listing (the element itself when
+    // unset).
+    #[serde(default, serialize_with = "redact")]
+    pub details: Option<String>,
+}
+
+// Custom exchanges have no known error envelope
+impl ApiEnvelope for Value {}
+
+fn default_product_type() -> String {
+    "spot".to_string()
+}
+
+pub async fn fetch(ctx: &FetchContext, config: &GenericRestConfig) -> Result<Vec<ReferenceData>> {
+    progress::println(format!("Processing {} {}...", config.name, config.product_type.to_uppercase()));
+    let response: Value = ctx.fetch_json(&config.url).await?;
+    let instruments = response
+        .pointer(&config.instruments)
+        .and_then(Value::as_array)
+        .with_context(|| format!("No instrument array at '{}' in {}", config.instruments, config.url))?;
+
+    let mut results = Vec::new();
+    match &config.details {
+        None => {
+            for inst in instruments {
+                let symbol = config.symbol.as_ref().map(|symbol| field(inst, symbol)).transpose()?;
+                results.extend(instrument(ctx, config, inst, symbol)?);
+            }
+        }
+        Some(details) => {
+            let pointer = config.symbol.as_deref().unwrap_or("");
+            let symbols = instruments.iter().map(|inst| field(inst, pointer)).collect::<Result<Vec<_>>>()?;
+            for (symbol, inst) in fetch_details(ctx, details, symbols).await? {
+                results.extend(instrument(ctx, config, &inst, Some(symbol))?);
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+/// The details of every symbol, in listing order; symbols whose call timed
+/// out are left out (see `FetchContext::fetch_symbol_json`).
+async fn fetch_details(ctx: &FetchContext, details: &str, symbols: Vec<String>) -> Result<Vec<(String, Value)>> {
+    let calls = symbols.into_iter().map(|symbol| async move {
+        let url = details.replace("{symbol}", &symbol);
+        let inst: Option<Value> = ctx.fetch_symbol_json(&symbol, &url).await?;
+        Ok::<_, anyhow::Error>(inst.map(|inst| (symbol, inst)))
+    });
+    // Concurrency is bounded by the context's request permits
+    let fetched = futures_util::future::try_join_all(calls).await?;
+    Ok(fetched.into_iter().flatten().collect())
+}
+
+// The row of one instrument, None when it isn't in the universe
+fn instrument(
+    ctx: &FetchContext,
+    config: &GenericRestConfig,
+    inst: &Value,
+    symbol: Option<String>,
+) -> Result<Option<ReferenceData>> {
+    let base = field(inst, &config.base)?;
+    let quote = field(inst, &config.quote)?;
+    if !ctx.wants(&base, &quote) {
+        return Ok(None);
+    }
+
+    let (tick_size, lot_size) = (field(inst, &config.tick_size)?, field(inst, &config.lot_size)?);
+    let context = || match &symbol {
+        Some(symbol) => format!("Parsing {} {} {}", config.name, config.product_type, symbol),
+        None => format!("Parsing {} {} {}/{}", config.name, config.product_type, base, quote),
+    };
+    let mut item = ReferenceData::new(
+        &config.name,
+        &config.product_type,
+        &base,
+        &quote,
+        parse_size(&tick_size).with_context(context)?,
+        parse_size(&lot_size).with_context(context)?,
+    )
+    .with_raw_sizes(&tick_size, &lot_size);
+    if let Some(symbol) = symbol {
+        item = item.with_native_symbol(&symbol);
+    }
+    Ok(Some(item))
+}
+
+// Strings are taken as is, numbers in their JSON spelling
+fn field(inst: &Value, pointer: &str) -> Result<String> {
+    match inst.pointer(pointer) {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(Value::Number(value)) => Ok(value.to_string()),
+        _ => bail!("Missing or non-scalar '{}' in instrument {}", pointer, inst),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchanges::{parse_checked, test_context};
+
+    // Lists two symbols Gemini-style and answers the details of btcusd only,
+    // the ethusd call hangs
+    async fn serve_gemini() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0; 4096];
+                    let read = stream.read(&mut request).await.unwrap_or(0);
+                    let request = String::from_utf8_lossy(&request[..read]).to_string();
+                    let body = if request.starts_with("GET /v1/symbols ") {
+                        r#"["btcusd", "ethusd"]"#
+                    } else if request.starts_with("GET /v1/symbols/details/btcusd ") {
+                        r#"{"base_currency": "BTC", "quote_currency": "USD",
+                            "quote_increment": 0.01, "tick_size": 1e-8}"#
+                    } else {
+                        std::future::pending::<()>().await;
+                        unreachable!()
+                    };
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn a_hanging_details_call_skips_only_its_symbol() {
+        let url = serve_gemini().await;
+        let config = GenericRestConfig {
+            name: "gemini".to_string(),
+            url: format!("{}/v1/symbols", url),
+            product_type: default_product_type(),
+            instruments: String::new(),
+            base: "/base_currency".to_string(),
+            quote: "/quote_currency".to_string(),
+            tick_size: "/quote_increment".to_string(),
+            lot_size: "/tick_size".to_string(),
+            symbol: None,
+            details: Some(format!("{}/v1/symbols/details/{{symbol}}", url)),
+        };
+        let ctx = test_context(&["--symbol-timeout", "200ms"], &["BTCUSD", "ETHUSD"]);
+        let rows = fetch(&ctx, &config).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].symbol, "BTC/USD-SPOT");
+        assert_eq!(rows[0].native_symbol.as_deref(), Some("btcusd"));
+        assert_eq!(rows[0].lot_size.to_string(), "0.00000001");
+        assert_eq!(*ctx.skipped.lock().unwrap(), ["ethusd"]);
+    }
+
+    // Without a known envelope only malformed JSON fails; an error object is
+    // caught by the missing instrument array instead
+    #[test]
+    fn any_json_passes_the_envelope() {
+        assert!(parse_checked::<Value>(r#"{"error": "maintenance"}"#).is_ok());
+        assert!(parse_checked::<Value>("<html>").is_err());
+    }
+}
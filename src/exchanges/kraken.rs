@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use super::{ApiEnvelope, FetchContext};
+use crate::model::{parse_size, ReferenceData};
+use crate::progress;
+
+// Kraken answers HTTP 200 even for failed calls, with the reason in `error`
+#[derive(Debug, Deserialize)]
+struct KrakenEnvelope<T> {
+    error: Vec<String>,
+    result: Option<T>,
+}
+
+impl<T> ApiEnvelope for KrakenEnvelope<T> {
+    fn check(&self) -> Result<()> {
+        if !self.error.is_empty() {
+            bail!("Kraken returned errors: {}", self.error.join(", "));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct KrakenAssetPair {
+    // e.g. XBT/USDT; absent for dark-pool pairs
+    #[serde(default)]
+    wsname: String,
+    lot_decimals: u32,
+    pair_decimals: u32,
+    // Newer responses carry the tick directly, pair_decimals is the fallback
+    #[serde(default)]
+    tick_size: Option<String>,
+    #[serde(default)]
+    status: String,
+    // Fee schedules as [30-day volume, percent] tiers, lowest volume first
+    #[serde(default)]
+    fees: Vec<[serde_json::Number; 2]>,
+    #[serde(default)]
+    fees_maker: Vec<[serde_json::Number; 2]>,
+}
+
+// The base tier's fee as a fraction, e.g. 0.26 (%) -> 0.0026
+fn base_tier_fee(tiers: &[[serde_json::Number; 2]]) -> Result<Option<Decimal>> {
+    let Some([_, percent]) = tiers.first() else {
+        return Ok(None);
+    };
+    Ok(Some((parse_size(&percent.to_string())? / Decimal::ONE_HUNDRED).normalize()))
+}
+
+#[derive(Debug, Deserialize)]
+struct KrakenTicker {
+    // Base volume and volume-weighted average price, [today, last 24 hours]
+    v: [String; 2],
+    p: [String; 2],
+}
+
+// Kraken's legacy names for a few assets
+const ASSET_ALIASES: &[(&str, &str)] = &[("XBT", "BTC"), ("XDG", "DOGE")];
+
+fn asset_name(name: &str) -> &str {
+    ASSET_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, asset)| asset)
+}
+
+/// Spot pairs only; Kraken's futures live on a separate API. AssetPairs
+/// returns every pair in one response, there is nothing to paginate.
+pub async fn fetch(ctx: &FetchContext) -> Result<Vec<ReferenceData>> {
+    progress::println("Processing Kraken SPOT...");
+    let url = "https://api.kraken.com/0/public/AssetPairs";
+    let response: KrakenEnvelope<HashMap<String, KrakenAssetPair>> = ctx.fetch_json(url).await?;
+
+    let mut results = Vec::new();
+    let pairs = response.result.context("Kraken returned neither errors nor a result")?;
+    for (name, pair) in pairs {
+        let Some((base, quote)) = pair.wsname.split_once('/') else {
+            continue;
+        };
+        let (base, quote) = (asset_name(base), asset_name(quote));
+        if !ctx.wants(base, quote) || (ctx.only_trading() && pair.status != "online") {
+            continue;
+        }
+
+        let context = || format!("Parsing kraken spot {}", name);
+        let tick_size = match &pair.tick_size {
+            Some(tick_size) => parse_size(tick_size).with_context(context)?,
+            None => Decimal::new(1, pair.pair_decimals),
+        };
+        let (maker_fee, taker_fee) = if ctx.with_fees() {
+            (base_tier_fee(&pair.fees_maker).with_context(context)?, base_tier_fee(&pair.fees).with_context(context)?)
+        } else {
+            (None, None)
+        };
+        // Only the tick is sent as a size, so it is the only raw one; the lot
+        // is derived from lot_decimals. pair_decimals is only worth checking
+        // when it didn't give the tick.
+        let lot_size = Decimal::new(1, pair.lot_decimals);
+        let item = ReferenceData {
+            maker_fee,
+            taker_fee,
+            price_precision: pair.tick_size.as_ref().map(|_| pair.pair_decimals),
+            tick_size_raw: pair.tick_size.clone(),
+            status: (!pair.status.is_empty()).then(|| pair.status.clone()),
+            ..ReferenceData::new("kraken", "spot", base, quote, tick_size, lot_size)
+        };
+        results.push(item.with_native_symbol(&name));
+    }
+    // The result is a map, keep the output stable
+    results.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+    Ok(results)
+}
+
+/// 24h quote volume of every pair by pair name, for --top-by-volume. Kraken
+/// only reports base volume, so it is valued at the 24h average price.
+pub async fn spot_volumes(ctx: &FetchContext) -> Result<HashMap<String, Decimal>> {
+    let url = "https://api.kraken.com/0/public/Ticker";
+    let response: KrakenEnvelope<HashMap<String, KrakenTicker>> = ctx.fetch_json(url).await?;
+    let tickers = response.result.context("Kraken returned neither errors nor a result")?;
+    tickers
+        .into_iter()
+        .map(|(name, ticker)| {
+            let context = || format!("Parsing the kraken volume of {}", name);
+            let volume = parse_size(&ticker.v[1]).with_context(context)?;
+            let average_price = parse_size(&ticker.p[1]).with_context(context)?;
+            Ok((name, volume * average_price))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exchanges::{fixture_dir, parse_checked, test_context};
+
+    type AssetPairs = KrakenEnvelope<HashMap<String, KrakenAssetPair>>;
+
+    #[test]
+    fn envelope_without_errors_passes() {
+        let body = r#"{"error": [], "result": {"XXBTZUSD": {"wsname": "XBT/USD", "lot_decimals": 8,
+            "pair_decimals": 1, "tick_size": "0.1", "status": "online"}}}"#;
+        let response: AssetPairs = parse_checked(body).unwrap();
+        assert_eq!(response.result.unwrap()["XXBTZUSD"].tick_size.as_deref(), Some("0.1"));
+    }
+
+    #[test]
+    fn envelope_with_errors_fails() {
+        let err = parse_checked::<AssetPairs>(r#"{"error": ["EQuery:Unknown asset pair"]}"#).unwrap_err();
+        assert_eq!(err.to_string(), "Kraken returned errors: EQuery:Unknown asset pair");
+    }
+
+    // Raw columns only hold what Kraken sent: a tick for some pairs, no lot
+    #[tokio::test]
+    async fn only_sent_sizes_are_raw() {
+        let ctx = test_context(&["--offline", &fixture_dir("recorded")], &["BTCUSD", "BTCUSDT"]);
+        let rows = fetch(&ctx).await.unwrap();
+        let raw: Vec<(&str, String, Option<&str>, Option<&str>)> = rows
+            .iter()
+            .map(|row| {
+                let raw_sizes = (row.tick_size_raw.as_deref(), row.lot_size_raw.as_deref());
+                (row.symbol.as_str(), row.tick_size.to_string(), raw_sizes.0, raw_sizes.1)
+            })
+            .collect();
+        assert_eq!(
+            raw,
+            [("BTC/USD-SPOT", "0.1".to_string(), Some("0.1"), None), ("BTC/USDT-SPOT", "0.1".to_string(), None, None)]
+        );
+    }
+
+    // SOL/USDT is cancel_only: kept with its status unless --only-trading
+    #[tokio::test]
+    async fn pairs_that_arent_online_need_only_trading_to_be_skipped() {
+        let fixtures = fixture_dir("recorded");
+        let statuses = |args: &[&str]| {
+            let ctx = test_context(args, &["BTCUSDT", "SOLUSDT"]);
+            async move {
+                let rows = fetch(&ctx).await.unwrap();
+                rows.into_iter().map(|row| (row.symbol, row.status.unwrap())).collect::<Vec<_>>()
+            }
+        };
+        let online = ("BTC/USDT-SPOT".to_string(), "online".to_string());
+        let cancel_only = ("SOL/USDT-SPOT".to_string(), "cancel_only".to_string());
+        assert_eq!(statuses(&["--offline", &fixtures]).await, [online.clone(), cancel_only]);
+        assert_eq!(statuses(&["--offline", &fixtures, "--only-trading"]).await, [online]);
+    }
+
+    #[tokio::test]
+    async fn error_array_fails_the_fetch() {
+        let ctx = test_context(&["--offline", &fixture_dir("kraken_error")], &["BTCUSDT"]);
+        let err = fetch(&ctx).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("Kraken returned errors: EGeneral:Temporary lockout"), "{:#}", err);
+    }
+}
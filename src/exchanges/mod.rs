@@ -0,0 +1,833 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+
+use crate::cli::{Cli, Pair};
+use crate::dns;
+use crate::model::ReferenceData;
+use crate::normalize;
+use crate::progress::{self, ExchangeProgress};
+
+pub mod binance;
+pub mod generic;
+pub mod kraken;
+pub mod okx;
+pub mod replay;
+mod volume;
+
+use generic::GenericRestConfig;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Exchange {
+    Binance,
+    Okx,
+    Kraken,
+    // Defined in the config's [[custom_exchange]] sections
+    Generic(&'static GenericRestConfig),
+}
+
+impl Exchange {
+    pub const ALL: &[Exchange] = &[Exchange::Binance, Exchange::Okx, Exchange::Kraken];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Exchange::Binance => "binance",
+            Exchange::Okx => "okx",
+            Exchange::Kraken => "kraken",
+            Exchange::Generic(config) => &config.name,
+        }
+    }
+
+    /// Lightweight endpoint answering whether the exchange is up. Custom
+    /// exchanges only have their instrument endpoint.
+    fn status_url(self) -> &'static str {
+        match self {
+            Exchange::Binance => "https://api.binance.com/api/v3/ping",
+            Exchange::Okx => "https://www.okx.com/api/v5/system/status",
+            Exchange::Kraken => "https://api.kraken.com/0/public/SystemStatus",
+            Exchange::Generic(config) => &config.url,
+        }
+    }
+
+    // Endpoint with the exchange's clock, and where the time (milliseconds
+    // since the epoch, or seconds for Kraken) is in the response
+    fn time_url(self) -> Option<(&'static str, &'static str, i64)> {
+        match self {
+            Exchange::Binance => Some(("https://api.binance.com/api/v3/time", "/serverTime", 1)),
+            Exchange::Okx => Some(("https://www.okx.com/api/v5/public/time", "/data/0/ts", 1)),
+            Exchange::Kraken => Some(("https://api.kraken.com/0/public/Time", "/result/unixtime", 1000)),
+            Exchange::Generic(_) => None,
+        }
+    }
+
+    /// Looks up a built-in exchange.
+    pub fn from_name(name: &str) -> Option<Exchange> {
+        Exchange::ALL.iter().copied().find(|exchange| exchange.name() == name)
+    }
+
+    async fn fetch(self, ctx: &FetchContext) -> Result<Vec<ReferenceData>> {
+        match self {
+            Exchange::Binance => binance::fetch(ctx).await,
+            Exchange::Okx => okx::fetch(ctx).await,
+            Exchange::Kraken => kraken::fetch(ctx).await,
+            Exchange::Generic(config) => generic::fetch(ctx, config).await,
+        }
+    }
+
+    // 24h quote volume by native symbol, spot only
+    async fn spot_volumes(self, ctx: &FetchContext) -> Result<HashMap<String, Decimal>> {
+        match self {
+            Exchange::Binance => binance::spot_volumes(ctx).await,
+            Exchange::Okx => okx::spot_volumes(ctx).await,
+            Exchange::Kraken => kraken::spot_volumes(ctx).await,
+            Exchange::Generic(config) => bail!("{} has no volume endpoint for --top-by-volume", config.name),
+        }
+    }
+}
+
+/// A response body that can report failure despite HTTP 200, e.g. OKX's
+/// `code` or Kraken's `error`. Bodies without such fields keep the default.
+pub trait ApiEnvelope {
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The pairs to collect: the symbol list, every pair of `--bases`, or every
+/// pair at all with `--top-by-volume`, narrowed to the `--quote-ccy` quotes.
+pub struct Universe {
+    // Concatenated, e.g. BTCUSDT
+    symbols: HashSet<String>,
+    bases: HashSet<String>,
+    quotes: HashSet<String>,
+    // --top-by-volume picks the pairs after fetching
+    everything: bool,
+}
+
+impl Universe {
+    fn new(cli: &Cli, symbols: Vec<String>) -> Self {
+        Self {
+            symbols: symbols.iter().map(|symbol| symbol.to_ascii_uppercase()).collect(),
+            bases: cli.bases.iter().cloned().collect(),
+            quotes: cli.quote_ccy.iter().cloned().collect(),
+            everything: cli.top_by_volume.is_some(),
+        }
+    }
+
+    // Case-insensitive, like the stored assets
+    fn contains(&self, base: &str, quote: &str) -> bool {
+        let (base, quote) = (normalize::asset(base), normalize::asset(quote));
+        if !self.quotes.is_empty() && !self.quotes.contains(&quote) {
+            return false;
+        }
+        if self.everything {
+            true
+        } else if self.bases.is_empty() {
+            self.symbols.contains(&format!("{}{}", base, quote))
+        } else {
+            self.bases.contains(&base)
+        }
+    }
+}
+
+/// Per-exchange fetch state. The HTTP client and the symbol universe are
+/// shared by all exchanges, the request permits are not: they bound how many
+/// calls one exchange has in flight, independently of how many exchanges run
+/// at once.
+pub struct FetchContext {
+    // Which exchange is fetched, for its errors
+    exchange: &'static str,
+    client: reqwest::Client,
+    universe: Arc<Universe>,
+    request_permits: Semaphore,
+    request_timeout: Duration,
+    // Per-symbol calls that take longer are skipped, and the symbols recorded
+    symbol_timeout: Duration,
+    skipped: Mutex<Vec<String>>,
+    // Retries of a request whose connection failed, and how many of them were
+    // for name resolution
+    connect_retries: u32,
+    dns_retries: AtomicU32,
+    unrecognized: Arc<Mutex<BTreeSet<String>>>,
+    phase_times: Mutex<PhaseTimes>,
+    api_headers: Mutex<BTreeMap<String, String>>,
+    with_fees: bool,
+    with_leverage: bool,
+    only_trading: bool,
+    required_permission: Option<String>,
+    target: Option<Pair>,
+    offline: Option<PathBuf>,
+    save_raw: Option<PathBuf>,
+    replay_rows: Option<PathBuf>,
+    save_rows: Option<PathBuf>,
+    top_by_volume: Option<usize>,
+}
+
+/// Where one exchange's fetch spent its time. Requests of one exchange may
+/// overlap, so the summed phases can exceed the total.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PhaseTimes {
+    pub total: Duration,
+    // Sending requests and downloading bodies
+    pub request: Duration,
+    // Deserializing responses
+    pub parse: Duration,
+}
+
+impl FetchContext {
+    fn new(
+        exchange: &'static str,
+        cli: &Cli,
+        client: reqwest::Client,
+        universe: Arc<Universe>,
+        symbol_concurrency: usize,
+        unrecognized: Arc<Mutex<BTreeSet<String>>>,
+    ) -> Self {
+        Self {
+            exchange,
+            client,
+            universe,
+            request_permits: Semaphore::new(symbol_concurrency),
+            request_timeout: cli.request_timeout,
+            symbol_timeout: cli.symbol_timeout,
+            skipped: Mutex::default(),
+            connect_retries: cli.connect_retries,
+            dns_retries: AtomicU32::new(0),
+            unrecognized,
+            phase_times: Mutex::default(),
+            api_headers: Mutex::default(),
+            with_fees: cli.with_fees,
+            with_leverage: cli.with_leverage,
+            only_trading: cli.only_trading,
+            required_permission: cli.require_permission.clone(),
+            target: cli.refresh_one.clone(),
+            offline: cli.offline.clone(),
+            save_raw: cli.save_raw.clone(),
+            replay_rows: cli.replay_rows.clone(),
+            save_rows: cli.save_rows.clone(),
+            top_by_volume: cli.top_by_volume,
+        }
+    }
+
+    /// Whether default maker/taker fees should be collected (`--with-fees`).
+    /// Fetchers whose fees need extra calls only make them when this is set.
+    pub fn with_fees(&self) -> bool {
+        self.with_fees
+    }
+
+    /// Whether the maximum leverage of derivatives should be collected
+    /// (`--with-leverage`).
+    pub fn with_leverage(&self) -> bool {
+        self.with_leverage
+    }
+
+    /// Whether instruments that aren't currently trading are skipped
+    /// (`--only-trading`). Fetchers without a status field keep everything.
+    pub fn only_trading(&self) -> bool {
+        self.only_trading
+    }
+
+    /// The account permission instruments must carry (`--require-permission`),
+    /// for exchanges that report permissions per instrument.
+    pub fn required_permission(&self) -> Option<&str> {
+        self.required_permission.as_deref()
+    }
+
+    /// Records a response value no fetcher models yet, e.g. a new filter type.
+    pub fn note_unrecognized(&self, what: String) {
+        self.unrecognized.lock().unwrap().insert(what);
+    }
+
+    /// The single pair of a `--refresh-one` run. The universe then only holds
+    /// that pair, fetchers with a targeted endpoint may use it instead.
+    pub fn target(&self) -> Option<&Pair> {
+        self.target.as_ref()
+    }
+
+    /// Whether the pair of `base` and `quote` is in the universe. Fetchers
+    /// check every listed pair on its own, so all quote legs of a base
+    /// (`BTCUSDT`, `BTCUSDC`, ...) in the universe are collected.
+    pub fn wants(&self, base: &str, quote: &str) -> bool {
+        self.universe.contains(base, quote)
+    }
+
+    /// GETs `url` and deserializes the body, failing on HTTP errors as well as
+    /// on errors the exchange reports inside a successful response. With
+    /// `--offline` the body saved by an earlier `--save-raw` run is used.
+    pub async fn fetch_json<T: DeserializeOwned + ApiEnvelope>(&self, url: &str) -> Result<T> {
+        let _permit = self.request_permits.acquire().await?;
+        self.fetch_json_with_permit(url).await
+    }
+
+    /// `fetch_json` for one symbol of a per-symbol enrichment, e.g. a details
+    /// endpoint. A call that takes longer than `--symbol-timeout` (waiting for
+    /// a request permit aside) doesn't fail the exchange: the symbol is logged,
+    /// recorded for the run summary and `None` returned.
+    pub async fn fetch_symbol_json<T: DeserializeOwned + ApiEnvelope>(
+        &self,
+        symbol: &str,
+        url: &str,
+    ) -> Result<Option<T>> {
+        let _permit = self.request_permits.acquire().await?;
+        match tokio::time::timeout(self.symbol_timeout, self.fetch_json_with_permit(url)).await {
+            Ok(parsed) => parsed.map(Some),
+            Err(_) => {
+                let timeout = humantime::format_duration(self.symbol_timeout);
+                progress::println(format!("Warning: Skipping {}: {} timed out after {}", symbol, url, timeout));
+                self.skipped.lock().unwrap().push(symbol.to_string());
+                Ok(None)
+            }
+        }
+    }
+
+    async fn fetch_json_with_permit<T: DeserializeOwned + ApiEnvelope>(&self, url: &str) -> Result<T> {
+        let started = Instant::now();
+        let mut body = match &self.offline {
+            Some(dir) => {
+                let path = raw_path(dir, url);
+                tokio::fs::read(&path)
+                    .await
+                    .with_context(|| format!("No saved response for {} at {}", url, path.display()))?
+            }
+            None => self.download_with_retries(url).await?,
+        };
+        if let Some(dir) = &self.save_raw {
+            let path = raw_path(dir, url);
+            tokio::fs::create_dir_all(dir)
+                .await
+                .and(tokio::fs::write(&path, &body).await)
+                .with_context(|| format!("Failed to save the response from {} to {}", url, path.display()))?;
+        }
+        let downloaded = Instant::now();
+        let parsed: Result<T> = parse_json(&mut body).with_context(|| format!("Invalid response from {}", url));
+
+        let mut phase_times = self.phase_times.lock().unwrap();
+        phase_times.request += downloaded - started;
+        phase_times.parse += downloaded.elapsed();
+        drop(phase_times);
+
+        let parsed = parsed?;
+        parsed.check().with_context(|| format!("Error response from {}", url))?;
+        Ok(parsed)
+    }
+
+    /// Downloads `url`, retrying with exponential backoff when the connection
+    /// can't be made (name resolution included), which is usually transient;
+    /// HTTP errors are not retried.
+    async fn download_with_retries(&self, url: &str) -> Result<Vec<u8>> {
+        let mut backoff = RETRY_BACKOFF;
+        for attempt in 0.. {
+            // A hung request would otherwise stall the exchange's whole fetch
+            let result = tokio::time::timeout(self.request_timeout, self.download(url))
+                .await
+                .map_err(|_| {
+                    anyhow!("Request to {} timed out after {}", url, humantime::format_duration(self.request_timeout))
+                })?;
+            let Err(err) = &result else {
+                return result;
+            };
+            let Some(cause) = err.downcast_ref::<reqwest::Error>().filter(|cause| cause.is_connect()) else {
+                return result;
+            };
+            if attempt == self.connect_retries {
+                return result;
+            }
+            let what = if dns::is_dns_error(cause) {
+                self.dns_retries.fetch_add(1, Ordering::Relaxed);
+                "Name resolution"
+            } else {
+                "Connecting"
+            };
+            progress::println(format!(
+                "Warning: {} failed for {}, retrying in {}: {}",
+                what,
+                url,
+                humantime::format_duration(backoff),
+                err.root_cause()
+            ));
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+        unreachable!("the retry loop only ends by returning")
+    }
+
+    async fn download(&self, url: &str) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Request to {} failed", url))?;
+        check_geo_block(self.exchange, &response)?;
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("Request to {} failed", url))?;
+        self.note_api_headers(url, response.headers());
+        let body = response
+            .bytes()
+            .await
+            .with_context(|| format!("Reading response from {} failed", url))?;
+        Ok(body.to_vec())
+    }
+
+    // Keeps the latest value of every rate-limit or version header
+    fn note_api_headers(&self, url: &str, headers: &reqwest::header::HeaderMap) {
+        let mut api_headers = self.api_headers.lock().unwrap();
+        for (name, value) in headers {
+            let name = name.as_str();
+            if !API_HEADER_PREFIXES.iter().any(|prefix| name.starts_with(prefix)) {
+                continue;
+            }
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            tracing::debug!(url, header = name, value, "API header");
+            api_headers.insert(name.to_string(), value);
+        }
+    }
+
+    /// Fetches `exchange`, or with `--replay-rows` loads the rows saved for it,
+    /// keeping only the universe's pairs.
+    async fn fetch_or_replay(&self, exchange: Exchange) -> Result<Vec<ReferenceData>> {
+        let Some(dir) = &self.replay_rows else {
+            let mut rows = dedup(exchange.name(), exchange.fetch(self).await?);
+            if let Some(n) = self.top_by_volume {
+                rows = volume::top(self, exchange, rows, n).await?;
+            }
+            if let Some(dir) = &self.save_rows {
+                replay::save(dir, exchange.name(), &rows)?;
+            }
+            return Ok(rows);
+        };
+        progress::println(format!("Replaying {} rows...", exchange.name()));
+        let rows = replay::load(dir, exchange.name())?;
+        Ok(rows.into_iter().filter(|row| self.wants(&row.base, &row.quote)).collect())
+    }
+
+    /// Fetches `exchange` and reports where the time went.
+    async fn fetch_timed(&self, exchange: Exchange) -> Outcome {
+        let progress = ExchangeProgress::start(exchange.name());
+        let started = Instant::now();
+        let result = self
+            .fetch_or_replay(exchange)
+            .instrument(tracing::info_span!("fetch", exchange = exchange.name()))
+            .await
+            .with_context(|| format!("Fetching {} failed", exchange.name()));
+        progress.finish(&result);
+        let timings = PhaseTimes {
+            total: started.elapsed(),
+            ..*self.phase_times.lock().unwrap()
+        };
+        let api_headers = std::mem::take(&mut *self.api_headers.lock().unwrap());
+        let dns_retries = self.dns_retries.swap(0, Ordering::Relaxed);
+        let skipped = std::mem::take(&mut *self.skipped.lock().unwrap());
+        Outcome { exchange, result, timings, api_headers, dns_retries, skipped }
+    }
+}
+
+// Wait before the first retry of a failed connection, doubled for each next one
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Drops instruments an exchange listed more than once in one response,
+/// keeping the first and warning when the copies disagree on their sizes.
+fn dedup(exchange: &str, rows: Vec<ReferenceData>) -> Vec<ReferenceData> {
+    let mut seen: HashMap<(String, String), (Decimal, Decimal)> = HashMap::new();
+    let mut kept = Vec::with_capacity(rows.len());
+    for row in rows {
+        let native = row.native_symbol.clone().unwrap_or_else(|| row.symbol.clone());
+        match seen.get(&(row.product_type.clone(), native.clone())) {
+            Some(&(tick_size, lot_size)) => {
+                if (tick_size, lot_size) != (row.tick_size, row.lot_size) {
+                    progress::println(format!(
+                        "Warning: {} listed {} {} twice (tick {} vs {}, lot {} vs {}), keeping the first",
+                        exchange, row.product_type, native, tick_size, row.tick_size, lot_size, row.lot_size
+                    ));
+                } else {
+                    tracing::debug!(exchange, symbol = native, "duplicate instrument dropped");
+                }
+            }
+            None => {
+                seen.insert((row.product_type.clone(), native), (row.tick_size, row.lot_size));
+                kept.push(row);
+            }
+        }
+    }
+    kept
+}
+
+/// Fails with an actionable error when `exchange` refuses to serve this
+/// region: HTTP 451, or an HTML page (a block or captcha page) with a 403 or
+/// success status where JSON was expected. HTML error pages of other statuses
+/// (a CDN's 404 or 5xx) are left to the usual HTTP error.
+fn check_geo_block(exchange: &str, response: &reqwest::Response) -> Result<()> {
+    let status = response.status();
+    if status == reqwest::StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS {
+        bail!("Geo-blocked for {} (HTTP 451); consider --proxy", exchange);
+    }
+    if status != reqwest::StatusCode::FORBIDDEN && !status.is_success() {
+        return Ok(());
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if content_type.starts_with("text/html") {
+        bail!(
+            "Likely geo-blocked for {}: an HTML page instead of JSON (HTTP {}); consider --proxy",
+            exchange,
+            status.as_u16()
+        );
+    }
+    Ok(())
+}
+
+// simd-json parses in place, which is why the body is mutable
+#[cfg(feature = "simd-json")]
+fn parse_json<T: DeserializeOwned>(body: &mut [u8]) -> Result<T> {
+    Ok(simd_json::serde::from_slice(body)?)
+}
+
+#[cfg(not(feature = "simd-json"))]
+fn parse_json<T: DeserializeOwned>(body: &mut [u8]) -> Result<T> {
+    Ok(serde_json::from_slice(body)?)
+}
+
+// Lower case, as reqwest normalizes header names
+const API_HEADER_PREFIXES: &[&str] = &[
+    "x-mbx-used-weight",
+    "x-mbx-order-count",
+    "x-ratelimit",
+    "ratelimit",
+    "x-api-version",
+];
+
+/// Where `--save-raw` keeps the body of `url`, e.g.
+/// `api_binance_com_api_v3_exchangeInfo.json`.
+fn raw_path(dir: &Path, url: &str) -> PathBuf {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    dir.join(format!("{}.json", url.replace(|c: char| !c.is_ascii_alphanumeric(), "_")))
+}
+
+/// What `--self-check` measured of one exchange.
+pub struct Probe {
+    pub latency: Duration,
+    /// Exchange clock minus the local one, for exchanges that publish their time
+    pub clock_offset_ms: Option<i64>,
+}
+
+// The local time is taken halfway through the request, which is when the
+// exchange most likely read its clock
+async fn clock_offset_ms(client: &reqwest::Client, (url, pointer, unit_ms): (&str, &str, i64)) -> Result<i64> {
+    let sent = chrono::Utc::now();
+    let body: serde_json::Value = client
+        .get(url)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Request to {} failed", url))?
+        .json()
+        .await
+        .with_context(|| format!("Invalid response from {}", url))?;
+    let received = chrono::Utc::now();
+    // OKX sends the timestamp as a string
+    let server_time = body
+        .pointer(pointer)
+        .and_then(|value| value.as_i64().or_else(|| value.as_str()?.parse().ok()))
+        .with_context(|| format!("No server time at {} in the response from {}", pointer, url))?;
+    let local_ms = sent.timestamp_millis() + (received - sent).num_milliseconds() / 2;
+    Ok(server_time * unit_ms - local_ms)
+}
+
+/// One exchange's fetch result.
+pub struct Outcome {
+    pub exchange: Exchange,
+    pub result: Result<Vec<ReferenceData>>,
+    pub timings: PhaseTimes,
+    /// Latest rate-limit and version headers, e.g. Binance's `x-mbx-used-weight-1m`
+    pub api_headers: BTreeMap<String, String>,
+    /// Requests retried because the exchange's host name didn't resolve
+    pub dns_retries: u32,
+    /// Symbols left out after their per-symbol call hit `--symbol-timeout`
+    pub skipped: Vec<String>,
+}
+
+/// The result of fetching a set of exchanges.
+pub struct FetchReport {
+    /// Each exchange's outcome in the order given
+    pub outcomes: Vec<Outcome>,
+    /// Distinct response values no fetcher models, e.g. `binance filter X`
+    pub unrecognized: BTreeSet<String>,
+}
+
+/// Fetches `exchanges`, at most `--exchange-concurrency` at a time. One
+/// exchange failing doesn't affect the others.
+pub async fn fetch_all(
+    cli: &Cli,
+    client: &reqwest::Client,
+    exchanges: &[Exchange],
+    symbols: Vec<String>,
+) -> FetchReport {
+    let universe = Arc::new(Universe::new(cli, symbols));
+    let unrecognized = Arc::new(Mutex::new(BTreeSet::new()));
+    progress::start(exchanges.len());
+    let outcomes = if cli.legacy {
+        fetch_sequential(cli, exchanges, universe, &unrecognized).await
+    } else {
+        fetch_concurrent(cli, client, exchanges, universe, &unrecognized).await
+    };
+    progress::finish();
+    let unrecognized = std::mem::take(&mut *unrecognized.lock().unwrap());
+    FetchReport { outcomes, unrecognized }
+}
+
+async fn fetch_concurrent(
+    cli: &Cli,
+    client: &reqwest::Client,
+    exchanges: &[Exchange],
+    universe: Arc<Universe>,
+    unrecognized: &Arc<Mutex<BTreeSet<String>>>,
+) -> Vec<Outcome> {
+    let exchange_permits = Arc::new(Semaphore::new(cli.exchange_concurrency as usize));
+
+    let handles: Vec<_> = exchanges
+        .iter()
+        .map(|&exchange| {
+            let exchange_permits = exchange_permits.clone();
+            let ctx = FetchContext::new(
+                exchange.name(),
+                cli,
+                client.clone(),
+                universe.clone(),
+                cli.symbol_concurrency as usize,
+                unrecognized.clone(),
+            );
+            let handle = tokio::spawn(async move {
+                // The semaphore is never closed
+                let _permit = exchange_permits.acquire_owned().await;
+                ctx.fetch_timed(exchange).await
+            });
+            (exchange, handle)
+        })
+        .collect();
+
+    let mut outcomes = Vec::new();
+    for (exchange, handle) in handles {
+        let outcome = handle.await.unwrap_or_else(|err| Outcome {
+            exchange,
+            result: Err(err.into()),
+            timings: PhaseTimes::default(),
+            api_headers: BTreeMap::new(),
+            dns_retries: 0,
+            skipped: Vec::new(),
+        });
+        outcomes.push(outcome);
+    }
+    outcomes
+}
+
+/// The pre-concurrency pipeline behind `--legacy`: one exchange after the
+/// other, one request at a time, each exchange with its own HTTP client.
+async fn fetch_sequential(
+    cli: &Cli,
+    exchanges: &[Exchange],
+    universe: Arc<Universe>,
+    unrecognized: &Arc<Mutex<BTreeSet<String>>>,
+) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    for &exchange in exchanges {
+        let ctx = FetchContext::new(exchange.name(), cli, cli.http_client(), universe.clone(), 1, unrecognized.clone());
+        outcomes.push(ctx.fetch_timed(exchange).await);
+    }
+    outcomes
+}
+
+/// Pings every exchange's status endpoint concurrently, returning the round
+/// trip latency per exchange in the order given.
+pub async fn self_check(client: &reqwest::Client, exchanges: &[Exchange]) -> Vec<(Exchange, Result<Probe>)> {
+    let handles: Vec<_> = exchanges
+        .iter()
+        .map(|&exchange| {
+            let client = client.clone();
+            let handle = tokio::spawn(async move {
+                let started = Instant::now();
+                client
+                    .get(exchange.status_url())
+                    .timeout(Duration::from_secs(10))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .with_context(|| format!("Request to {} failed", exchange.status_url()))?;
+                let latency = started.elapsed();
+                let clock_offset_ms = match exchange.time_url() {
+                    Some(endpoint) => Some(clock_offset_ms(&client, endpoint).await?),
+                    None => None,
+                };
+                Ok(Probe { latency, clock_offset_ms })
+            });
+            (exchange, handle)
+        })
+        .collect();
+
+    let mut outcomes = Vec::new();
+    for (exchange, handle) in handles {
+        let outcome = handle.await.map_err(anyhow::Error::from).and_then(|result| result);
+        outcomes.push((exchange, outcome));
+    }
+    outcomes
+}
+
+/// A fetch context for tests, with the options of `args` (e.g. `--offline`
+/// and a fixture directory) and `symbols` as the universe.
+#[cfg(test)]
+pub fn test_context(args: &[&str], symbols: &[&str]) -> FetchContext {
+    use clap::Parser;
+
+    let cli = Cli::parse_from(std::iter::once("crypto_refdata").chain(args.iter().copied()));
+    let universe = Arc::new(Universe::new(&cli, symbols.iter().map(|symbol| symbol.to_string()).collect()));
+    FetchContext::new("test", &cli, cli.http_client(), universe, 1, Arc::default())
+}
+
+/// Parses and checks a response body the way `fetch_json` does.
+#[cfg(test)]
+pub fn parse_checked<T: DeserializeOwned + ApiEnvelope>(body: &str) -> Result<T> {
+    let parsed: T = parse_json(&mut body.as_bytes().to_vec())?;
+    parsed.check()?;
+    Ok(parsed)
+}
+
+/// The directory of a checked-in fixture under `tests/fixtures`.
+#[cfg(test)]
+pub fn fixture_dir(name: &str) -> String {
+    format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Answers every connection with the recorded HTTP response `name` of the
+    // fixture directory `dir`
+    async fn serve_recorded(dir: &str, name: &str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let response = std::fs::read(format!("{}/{}", fixture_dir(dir), name)).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).await;
+                let _ = stream.write_all(&response).await;
+            }
+        });
+        format!("http://{}/api/v3/exchangeInfo", addr)
+    }
+
+    #[tokio::test]
+    async fn http_451_is_reported_as_a_geo_block() {
+        let url = serve_recorded("geo_block", "451.http").await;
+        let mut ctx = test_context(&[], &[]);
+        ctx.exchange = "binance";
+        let err = ctx.fetch_json::<serde_json::Value>(&url).await.unwrap_err();
+        assert_eq!(err.to_string(), "Geo-blocked for binance (HTTP 451); consider --proxy");
+    }
+
+    #[tokio::test]
+    async fn html_instead_of_json_is_reported_as_a_geo_block() {
+        let url = serve_recorded("geo_block", "html_200.http").await;
+        let mut ctx = test_context(&[], &[]);
+        ctx.exchange = "binance";
+        let err = ctx.fetch_json::<serde_json::Value>(&url).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Likely geo-blocked for binance: an HTML page instead of JSON (HTTP 200); consider --proxy"
+        );
+    }
+
+    // A CDN's error page is an outage, not a block
+    #[tokio::test]
+    async fn html_error_pages_are_plain_http_errors() {
+        let url = serve_recorded("geo_block", "html_503.http").await;
+        let err = test_context(&[], &[]).fetch_json::<serde_json::Value>(&url).await.unwrap_err();
+        assert_eq!(err.to_string(), format!("Request to {} failed", url));
+        assert!(format!("{:#}", err).contains("503 Service Unavailable"), "{:#}", err);
+    }
+
+    // Fails the first `failures` lookups like an unresolvable name, then
+    // resolves everything to localhost
+    struct FlakyResolver {
+        failures: AtomicU32,
+    }
+
+    impl reqwest::dns::Resolve for FlakyResolver {
+        fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+            let failed = self
+                .failures
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| left.checked_sub(1))
+                .is_ok();
+            let host = name.as_str().to_string();
+            Box::pin(async move {
+                if failed {
+                    let source = std::io::Error::other("failed to lookup address information");
+                    return Err(Box::new(dns::DnsError { host, source }) as _);
+                }
+                let addrs: reqwest::dns::Addrs = Box::new(std::iter::once(([127, 0, 0, 1], 0).into()));
+                Ok(addrs)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn dns_failures_are_retried_and_counted() {
+        let url = serve_recorded("dns_retry", "ok.http").await.replace("127.0.0.1", "flaky.invalid");
+        let resolver = FlakyResolver { failures: AtomicU32::new(2) };
+        let client = reqwest::Client::builder().dns_resolver(Arc::new(resolver)).build().unwrap();
+        let mut ctx = test_context(&["--connect-retries", "2"], &[]);
+        ctx.client = client;
+        let body: serde_json::Value = ctx.fetch_json(&url).await.unwrap();
+        assert_eq!(body["result"]["markets"], serde_json::json!([]));
+        assert_eq!(ctx.dns_retries.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn dns_failures_beyond_the_retries_fail() {
+        let url = "http://flaky.invalid:1/";
+        let resolver = FlakyResolver { failures: AtomicU32::new(2) };
+        let client = reqwest::Client::builder().dns_resolver(Arc::new(resolver)).build().unwrap();
+        let mut ctx = test_context(&["--connect-retries", "1"], &[]);
+        ctx.client = client;
+        let err = ctx.fetch_json::<serde_json::Value>(url).await.unwrap_err();
+        assert!(err.chain().any(dns::is_dns_error), "{:#}", err);
+        assert_eq!(ctx.dns_retries.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn duplicate_listings_keep_the_first() {
+        let ctx = test_context(&["--offline", &fixture_dir("duplicate")], &["BTCUSDT", "ETHUSDT"]);
+        let rows = ctx.fetch_or_replay(Exchange::Binance).await.unwrap();
+        let spot: Vec<(&str, String)> = rows
+            .iter()
+            .filter(|row| row.product_type == "spot")
+            .map(|row| (row.symbol.as_str(), row.tick_size.to_string()))
+            .collect();
+        // BTCUSDT's copies disagree on the tick, ETHUSDT's are identical
+        assert_eq!(spot, [("BTC/USDT-SPOT", "0.01".to_string()), ("ETH/USDT-SPOT", "0.01".to_string())]);
+        assert_eq!(rows.iter().filter(|row| row.product_type == "perp").count(), 2);
+    }
+
+    #[test]
+    fn spot_and_perp_of_one_symbol_are_not_duplicates() {
+        let spot = ReferenceData::new("binance", "spot", "BTC", "USDT", Decimal::ONE, Decimal::ONE);
+        let perp = ReferenceData::new("binance", "perp", "BTC", "USDT", Decimal::ONE, Decimal::ONE);
+        let rows = vec![spot.with_native_symbol("BTCUSDT"), perp.with_native_symbol("BTCUSDT")];
+        assert_eq!(dedup("binance", rows).len(), 2);
+    }
+}
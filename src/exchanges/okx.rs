@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use super::{ApiEnvelope, FetchContext};
+use crate::model::{parse_size, ReferenceData};
+use crate::progress;
+
+// OKX structures
+#[derive(Debug, Deserialize)]
+struct OkxResponse<T> {
+    // "0" on success
+    code: String,
+    #[serde(default)]
+    msg: String,
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+}
+
+impl<T> ApiEnvelope for OkxResponse<T> {
+    fn check(&self) -> Result<()> {
+        if self.code != "0" {
+            bail!("OKX returned code {}: {}", self.code, self.msg);
+        }
+        Ok(())
+    }
+}
+
+// A targeted lookup, where 51001 (the instId doesn't exist) just means no
+// instruments rather than a failed request
+#[derive(Debug, Deserialize)]
+#[serde(transparent)]
+struct OkxLookup<T>(OkxResponse<T>);
+
+impl<T> ApiEnvelope for OkxLookup<T> {
+    fn check(&self) -> Result<()> {
+        if self.0.code == "51001" {
+            return Ok(());
+        }
+        self.0.check()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxInstrument {
+    inst_id: String,
+    base_ccy: String,
+    quote_ccy: String,
+    tick_sz: String,
+    lot_sz: String,
+    // Minimum order size, in the unit of lotSz (contracts for swaps)
+    #[serde(default)]
+    min_sz: String,
+    // Contract value and its currency, empty for spot
+    #[serde(default)]
+    ct_val: String,
+    #[serde(default)]
+    ct_val_ccy: String,
+    // Instrument family and underlying, empty for spot
+    #[serde(default)]
+    inst_family: String,
+    #[serde(default)]
+    uly: String,
+    // live, suspend, preopen, ...
+    #[serde(default)]
+    state: String,
+    // Maximum leverage, empty for spot
+    #[serde(default)]
+    lever: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxTicker {
+    inst_id: String,
+    // 24h volume in the quote currency for spot
+    vol_ccy24h: String,
+}
+
+pub async fn fetch(ctx: &FetchContext) -> Result<Vec<ReferenceData>> {
+    if let Some(pair) = ctx.target() {
+        match fetch_okx_instrument(ctx, &format!("{}-{}", pair.base, pair.quote)).await {
+            Ok(results) => return Ok(results),
+            Err(err) => eprintln!("Warning: Targeted OKX fetch failed, fetching the full lists: {:#}", err),
+        }
+    }
+    let (mut results, perps) = tokio::try_join!(fetch_spot(ctx, ""), fetch_futures(ctx, ""))?;
+    results.extend(perps);
+    Ok(results)
+}
+
+/// Fetches only the spot pair and perpetual swap of `inst_id` (e.g.
+/// `BTC-USDT`) instead of the whole instrument lists. Swaps are looked up by
+/// instrument family, which is the spot instId for linear and inverse swaps.
+async fn fetch_okx_instrument(ctx: &FetchContext, inst_id: &str) -> Result<Vec<ReferenceData>> {
+    let spot_filter = format!("&instId={}", inst_id);
+    let swap_filter = format!("&instFamily={}", inst_id);
+    let (mut results, perps) = tokio::try_join!(fetch_spot(ctx, &spot_filter), fetch_futures(ctx, &swap_filter))?;
+    results.extend(perps);
+    Ok(results)
+}
+
+// `filter` is appended to the query string, e.g. `&instId=BTC-USDT`; a
+// filtered request is a targeted lookup
+async fn instruments(ctx: &FetchContext, inst_type: &str, filter: &str) -> Result<Vec<OkxInstrument>> {
+    let url = format!("https://www.okx.com/api/v5/public/instruments?instType={}{}", inst_type, filter);
+    if filter.is_empty() {
+        let response: OkxResponse<OkxInstrument> = ctx.fetch_json(&url).await?;
+        Ok(response.data)
+    } else {
+        let response: OkxLookup<OkxInstrument> = ctx.fetch_json(&url).await?;
+        Ok(response.0.data)
+    }
+}
+
+async fn fetch_spot(ctx: &FetchContext, filter: &str) -> Result<Vec<ReferenceData>> {
+    progress::println("Processing OKX SPOT...");
+    let mut results = Vec::new();
+    for inst in instruments(ctx, "SPOT", filter).await? {
+        if !ctx.wants(&inst.base_ccy, &inst.quote_ccy) || (ctx.only_trading() && inst.state != "live") {
+            continue;
+        }
+        let context = || format!("Parsing okx spot {}", inst.inst_id);
+
+        let item = ReferenceData {
+            min_qty: parse_min(&inst.min_sz).with_context(context)?,
+            status: non_empty(inst.state.clone()),
+            ..ReferenceData::new(
+                "okx",
+                "spot",
+                &inst.base_ccy,
+                &inst.quote_ccy,
+                parse_size(&inst.tick_sz).with_context(context)?,
+                parse_size(&inst.lot_sz).with_context(context)?,
+            )
+        };
+        results.push(item.with_raw_sizes(&inst.tick_sz, &inst.lot_sz).with_native_symbol(&inst.inst_id));
+    }
+
+    Ok(results)
+}
+
+async fn fetch_futures(ctx: &FetchContext, filter: &str) -> Result<Vec<ReferenceData>> {
+    progress::println("Processing OKX PERP...");
+    let mut results = Vec::new();
+    for inst in instruments(ctx, "SWAP", filter).await? {
+        // Swap ids look like BTC-USDT-SWAP and carry no baseCcy/quoteCcy
+        let Some(pair) = inst.inst_id.strip_suffix("-SWAP") else {
+            continue;
+        };
+        let Some((base, quote)) = pair.split_once('-') else {
+            continue;
+        };
+        if !ctx.wants(base, quote) || (ctx.only_trading() && inst.state != "live") {
+            continue;
+        }
+        let context = || format!("Parsing okx perp {}", inst.inst_id);
+
+        // ctVal is only a base quantity for linear contracts
+        let contract_size = if inst.ct_val_ccy == base {
+            Some(parse_size(&inst.ct_val).with_context(context)?)
+        } else {
+            None
+        };
+
+        let max_leverage = if ctx.with_leverage() && !inst.lever.is_empty() {
+            Some(parse_size(&inst.lever).with_context(context)?)
+        } else {
+            None
+        };
+
+        // minSz counts contracts, min_qty is in base units like everywhere else;
+        // inverse contracts have no base unit to convert to
+        let min_qty = parse_min(&inst.min_sz)
+            .with_context(context)?
+            .zip(contract_size)
+            .map(|(min_contracts, contract_size)| (min_contracts * contract_size).normalize());
+
+        let item = ReferenceData {
+            min_qty,
+            contract_size,
+            max_leverage,
+            inst_family: non_empty(inst.inst_family),
+            uly: non_empty(inst.uly),
+            status: non_empty(inst.state),
+            ..ReferenceData::new(
+                "okx",
+                "perp",
+                base,
+                quote,
+                parse_size(&inst.tick_sz).with_context(context)?,
+                parse_size(&inst.lot_sz).with_context(context)?,
+            )
+        };
+        results.push(
+            item.with_min_order_base_qty()
+                .with_raw_sizes(&inst.tick_sz, &inst.lot_sz)
+                .with_native_symbol(&inst.inst_id),
+        );
+    }
+
+    Ok(results)
+}
+
+/// 24h quote volume of every spot pair by instId, for --top-by-volume.
+pub async fn spot_volumes(ctx: &FetchContext) -> Result<HashMap<String, Decimal>> {
+    let url = "https://www.okx.com/api/v5/market/tickers?instType=SPOT";
+    let response: OkxResponse<OkxTicker> = ctx.fetch_json(url).await?;
+    response
+        .data
+        .into_iter()
+        .map(|ticker| {
+            let volume = parse_size(&ticker.vol_ccy24h)
+                .with_context(|| format!("Parsing the okx volume of {}", ticker.inst_id))?;
+            Ok((ticker.inst_id, volume))
+        })
+        .collect()
+}
+
+fn parse_min(value: &str) -> Result<Option<Decimal>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    parse_size(value).map(Some)
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::exchanges::{fixture_dir, parse_checked, test_context};
+
+    #[tokio::test]
+    async fn lot_and_min_size_are_both_stored() {
+        let ctx = test_context(&["--offline", &fixture_dir("recorded")], &["BTCUSDT", "ETHUSDT", "BTCUSD"]);
+        let mut conn = db::open_in_memory().unwrap();
+        db::save_to_sqlite(&mut conn, fetch(&ctx).await.unwrap(), None, chrono::Utc::now(), &[], &[]).unwrap();
+        let stored: Vec<(String, String, Option<String>)> = db::instrument_rows(&conn, &Default::default())
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.native_symbol.unwrap(), row.lot_size.to_string(), row.min_qty.map(|min| min.to_string())))
+            .collect();
+        let expected = [
+            ("BTC-USDT", "0.00000001", Some("0.00001")),
+            ("ETH-USDT", "0.000001", Some("0.0001")),
+            // Contracts: 0.01 x 0.01 BTC, 1 x 0.1 ETH, and an inverse swap without a base minimum
+            ("BTC-USDT-SWAP", "0.01", Some("0.0001")),
+            ("BTC-USD-SWAP", "1", None),
+            ("ETH-USDT-SWAP", "1", Some("0.1")),
+        ];
+        let expected: Vec<(String, String, Option<String>)> = expected
+            .iter()
+            .map(|(native, lot, min)| (native.to_string(), lot.to_string(), min.map(str::to_string)))
+            .collect();
+        assert_eq!(stored, expected);
+    }
+
+    // ETH-USDT's tick carries its unit, which is rejected rather than guessed at
+    #[tokio::test]
+    async fn size_errors_name_the_instrument() {
+        let ctx = test_context(&["--offline", &fixture_dir("size_quirk")], &["BTCUSDT", "ETHUSDT"]);
+        let err = fetch_spot(&ctx, "").await.unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.starts_with("Parsing okx spot ETH-USDT: Invalid size value '0.01 USDT'"), "{}", message);
+    }
+
+    #[test]
+    fn envelope_with_code_0_passes() {
+        let body = r#"{"code": "0", "msg": "", "data": [{"instId": "BTC-USDT", "baseCcy": "BTC", "quoteCcy": "USDT",
+            "tickSz": "0.1", "lotSz": "0.00000001"}]}"#;
+        let response: OkxResponse<OkxInstrument> = parse_checked(body).unwrap();
+        assert_eq!(response.data[0].inst_id, "BTC-USDT");
+    }
+
+    #[test]
+    fn envelope_with_error_code_fails() {
+        let body = r#"{"code": "50011", "msg": "Too Many Requests", "data": []}"#;
+        let err = parse_checked::<OkxResponse<OkxInstrument>>(body).unwrap_err();
+        assert_eq!(err.to_string(), "OKX returned code 50011: Too Many Requests");
+        assert!(parse_checked::<OkxLookup<OkxInstrument>>(body).is_err());
+    }
+
+    const UNKNOWN_INST_ID: &str = r#"{"code": "51001", "msg": "Instrument ID does not exist", "data": []}"#;
+
+    #[test]
+    fn unknown_inst_id_is_empty_for_lookups_only() {
+        let lookup: OkxLookup<OkxInstrument> = serde_json::from_str(UNKNOWN_INST_ID).unwrap();
+        assert!(lookup.check().is_ok());
+        assert!(lookup.0.data.is_empty());
+        let full: OkxResponse<OkxInstrument> = serde_json::from_str(UNKNOWN_INST_ID).unwrap();
+        assert!(full.check().is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Normalized rows saved per exchange with `--save-rows`, in a shape that
+//! `--replay-rows` feeds back through the pipeline instead of fetching. The
+//! same shape is what `--post-process` programs read and write.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::model::ReferenceData;
+
+// The fetched fields of a row; symbols, canonical and quote kind are derived
+// from them again on load
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SavedRow {
+    // Only in the multi-exchange form of `to_json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exchange: Option<String>,
+    product_type: String,
+    base: String,
+    quote: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    native_symbol: Option<String>,
+    tick_size: Decimal,
+    lot_size: Decimal,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tick_size_raw: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lot_size_raw: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_qty: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    contract_size: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_order_base_qty: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inst_family: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uly: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_price: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_price: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    maker_fee: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    taker_fee: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    permissions: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_leverage: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+}
+
+/// Where the rows of `exchange` are kept, e.g. `okx.rows.json`. The suffix
+/// keeps them apart from `--save-raw` bodies in a shared directory.
+fn rows_path(dir: &Path, exchange: &str) -> PathBuf {
+    dir.join(format!("{}.rows.json", exchange))
+}
+
+impl SavedRow {
+    fn of(row: &ReferenceData, exchange: Option<String>) -> Self {
+        SavedRow {
+            exchange,
+            product_type: row.product_type.clone(),
+            base: row.base.clone(),
+            quote: row.quote.clone(),
+            native_symbol: row.native_symbol.clone(),
+            tick_size: row.tick_size,
+            lot_size: row.lot_size,
+            tick_size_raw: row.tick_size_raw.clone(),
+            lot_size_raw: row.lot_size_raw.clone(),
+            min_qty: row.min_qty,
+            contract_size: row.contract_size,
+            min_order_base_qty: row.min_order_base_qty,
+            inst_family: row.inst_family.clone(),
+            uly: row.uly.clone(),
+            min_price: row.min_price,
+            max_price: row.max_price,
+            maker_fee: row.maker_fee,
+            taker_fee: row.taker_fee,
+            permissions: row.permissions.clone(),
+            max_leverage: row.max_leverage,
+            status: row.status.clone(),
+        }
+    }
+
+    fn into_row(self, exchange: &str) -> ReferenceData {
+        ReferenceData {
+            native_symbol: self.native_symbol,
+            tick_size_raw: self.tick_size_raw,
+            lot_size_raw: self.lot_size_raw,
+            min_qty: self.min_qty,
+            contract_size: self.contract_size,
+            min_order_base_qty: self.min_order_base_qty,
+            inst_family: self.inst_family,
+            uly: self.uly,
+            min_price: self.min_price,
+            max_price: self.max_price,
+            maker_fee: self.maker_fee,
+            taker_fee: self.taker_fee,
+            permissions: self.permissions,
+            max_leverage: self.max_leverage,
+            status: self.status,
+            ..ReferenceData::new(exchange, &self.product_type, &self.base, &self.quote, self.tick_size, self.lot_size)
+        }
+    }
+}
+
+/// Writes the rows fetched from `exchange` as a JSON array.
+pub fn save(dir: &Path, exchange: &str, rows: &[ReferenceData]) -> Result<()> {
+    let saved: Vec<SavedRow> = rows.iter().map(|row| SavedRow::of(row, None)).collect();
+    let path = rows_path(dir, exchange);
+    std::fs::create_dir_all(dir)
+        .and_then(|()| std::fs::write(&path, serde_json::to_vec_pretty(&saved)?))
+        .with_context(|| format!("Failed to save the {} rows to {}", exchange, path.display()))
+}
+
+/// Reads the rows an earlier `--save-rows` run saved for `exchange`.
+pub fn load(dir: &Path, exchange: &str) -> Result<Vec<ReferenceData>> {
+    let path = rows_path(dir, exchange);
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("No saved rows for {} at {}", exchange, path.display()))?;
+    let saved: Vec<SavedRow> =
+        serde_json::from_str(&text).with_context(|| format!("Invalid saved rows in {}", path.display()))?;
+    Ok(saved.into_iter().map(|row| row.into_row(exchange)).collect())
+}
+
+/// Rows of any exchanges as a JSON array in the saved shape, each with its
+/// `exchange`.
+pub fn to_json(rows: &[ReferenceData]) -> Result<Vec<u8>> {
+    let saved: Vec<SavedRow> = rows.iter().map(|row| SavedRow::of(row, Some(row.exchange.clone()))).collect();
+    Ok(serde_json::to_vec(&saved)?)
+}
+
+/// Parses rows written by `to_json` (or a program imitating it), which must
+/// all name their exchange.
+pub fn from_json(body: &[u8]) -> Result<Vec<ReferenceData>> {
+    let saved: Vec<SavedRow> = serde_json::from_slice(body)?;
+    saved
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let exchange = row.exchange.clone().with_context(|| format!("Row {} has no exchange", index))?;
+            Ok(row.into_row(&exchange))
+        })
+        .collect()
+}
@@ -0,0 +1,65 @@
+//! `--top-by-volume`: narrowing an exchange's rows to its most traded pairs.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use rust_decimal::Decimal;
+
+use super::{Exchange, FetchContext};
+use crate::model::ReferenceData;
+
+// Volume rankings barely move between daemon refreshes, and the ticker
+// endpoints are among the heaviest calls on the exchanges' rate limits
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+type Volumes = Arc<HashMap<String, Decimal>>;
+
+static CACHE: Mutex<Vec<(&'static str, Instant, Volumes)>> = Mutex::new(Vec::new());
+
+/// Keeps the rows of the `n` spot pairs with the highest 24h quote volume,
+/// along with the other product types (e.g. the perp) of those pairs.
+pub async fn top(
+    ctx: &FetchContext,
+    exchange: Exchange,
+    rows: Vec<ReferenceData>,
+    n: usize,
+) -> Result<Vec<ReferenceData>> {
+    let volumes = spot_volumes(ctx, exchange).await?;
+    let mut ranked: Vec<(Decimal, &ReferenceData)> = rows
+        .iter()
+        .filter(|row| row.product_type == "spot")
+        .filter_map(|row| Some((*volumes.get(row.native_symbol.as_deref()?)?, row)))
+        .collect();
+    // Highest first, ties by symbol so the selection is stable
+    ranked.sort_by(|(a_volume, a), (b_volume, b)| b_volume.cmp(a_volume).then_with(|| a.symbol.cmp(&b.symbol)));
+    let pairs: HashSet<(String, String)> = ranked
+        .into_iter()
+        .take(n)
+        .map(|(_, row)| (row.base.clone(), row.quote.clone()))
+        .collect();
+    Ok(rows
+        .into_iter()
+        .filter(|row| pairs.contains(&(row.base.clone(), row.quote.clone())))
+        .collect())
+}
+
+async fn spot_volumes(ctx: &FetchContext, exchange: Exchange) -> Result<Volumes> {
+    let name = exchange.name();
+    let cached = CACHE
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(cached, fetched_at, _)| *cached == name && fetched_at.elapsed() < CACHE_TTL)
+        .map(|(_, _, volumes)| volumes.clone());
+    if let Some(volumes) = cached {
+        return Ok(volumes);
+    }
+
+    let volumes = Arc::new(exchange.spot_volumes(ctx).await?);
+    let mut cache = CACHE.lock().unwrap();
+    cache.retain(|(cached, _, _)| *cached != name);
+    cache.push((name, Instant::now(), volumes.clone()));
+    Ok(volumes)
+}
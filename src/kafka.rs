@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use serde_json::Value as JsonValue;
+
+/// Publishes every changed row to `topic`, keyed by its canonical symbol.
+/// Kafka being unavailable is only a warning, the run carries on.
+pub async fn publish(brokers: &str, topic: &str, rows: &[JsonValue]) {
+    if let Err(err) = try_publish(brokers, topic, rows).await {
+        eprintln!("Warning: {:#}", err);
+    }
+}
+
+async fn try_publish(brokers: &str, topic: &str, rows: &[JsonValue]) -> Result<()> {
+    let producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        // Bounds how long an unreachable cluster can hold up the run
+        .set("message.timeout.ms", "5000")
+        .create()
+        .context("Failed to create Kafka producer")?;
+
+    for row in rows {
+        let key = row["canonical"].as_str().unwrap_or_default();
+        let payload = row.to_string();
+        producer
+            .send(FutureRecord::to(topic).key(key).payload(&payload), Duration::ZERO)
+            .await
+            .map_err(|(err, _)| err)
+            .with_context(|| format!("Publishing to Kafka topic {} failed", topic))?;
+    }
+    Ok(())
+}
@@ -1,289 +1,778 @@
-use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
-use serde::Deserialize;
-
-#[derive(Debug)]
-struct ReferenceData {
-    product_type: String,
-    exchange: String,
-    symbol: String,
-    tick_size: String,
-    lot_size: String,
-}
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use clap::Parser;
+use serde_json::Value as JsonValue;
+use tokio::sync::broadcast;
+use tracing_subscriber::EnvFilter;
+
+mod alerts;
+mod changes;
+mod cli;
+mod config;
+mod db;
+mod display;
+mod dns;
+mod exchanges;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod manifest;
+mod metrics;
+mod model;
+mod normalize;
+mod output;
+mod overrides;
+mod postprocess;
+mod progress;
+mod render;
+mod server;
+mod symbols;
+mod validate;
+
+use rusqlite::Connection;
+
+use cli::{Cli, Command, LogFormat};
+use config::Config;
+use exchanges::Exchange;
+use model::parse_size;
+use output::OutputFormat;
 
-// Binance Spot structures
-#[derive(Debug, Deserialize)]
-struct BinanceSpotExchangeInfo {
-    symbols: Vec<BinanceSpotSymbol>,
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(|| progress::LogWriter);
+    match cli.log_format {
+        LogFormat::Text => logs.init(),
+        LogFormat::Json => logs.json().init(),
+    }
+    progress::init(cli.progress);
+    if cli.output.iter().filter(|format| format.exporter().is_some()).count() > 1 {
+        bail!("Only one non-SQLite --output per run, they would share --out-file");
+    }
+    if cli.legacy {
+        eprintln!("Warning: --legacy is deprecated and will be removed in a future release");
+    }
+    let config = match &cli.config_url {
+        Some(url) => Config::load_remote(url, &cli.config_cache, cli.config.as_deref()).await?,
+        None => Config::load(cli.config.as_deref())?,
+    };
+    display::init(&config.display_scale);
+    normalize::init(&config.stable_aliases)?;
+    if cli.dump_config {
+        return dump_config(&cli, &config);
+    }
+    if cli.self_check {
+        return self_check(&cli, &config).await;
+    }
+    match cli.command {
+        Some(Command::Export { since_id }) => export(&cli, &config, &db::open()?, since_id),
+        Some(Command::Query { ref tag }) => query(&cli, &db::open()?, tag.clone()),
+        Some(Command::Migrate { dry_run }) => migrate(dry_run),
+        Some(Command::BestTick { ref canonical }) => best_tick(&config, canonical),
+        Some(Command::AddAlias { ref exchange, ref old, ref new }) => {
+            db::add_alias(&db::open()?, exchange, old, new)?;
+            println!("Alias added on {}: {} -> {}", exchange, old, new);
+            Ok(())
+        }
+        None if cli.verify_idempotent => verify_idempotent(&cli, &config).await,
+        None => run(&cli, &config).await,
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct BinanceSpotSymbol {
-    symbol: String,
-    baseAsset: String,
-    quoteAsset: String,
-    filters: Vec<BinanceFilter>,
+/// Prints the settings this invocation runs with: the config (defaults filled
+/// in, the exchanges resolved) and the command-line options.
+fn dump_config(cli: &Cli, config: &Config) -> Result<()> {
+    let mut settings = toml::Table::new();
+    let mut file = toml::Table::try_from(config)?;
+    let exchanges = config.enabled_exchanges()?.iter().map(|exchange| exchange.name().into()).collect();
+    file.insert("exchanges".to_string(), toml::Value::Array(exchanges));
+    settings.insert("config".to_string(), file.into());
+    settings.insert("cli".to_string(), toml::Value::try_from(cli)?);
+    if let Some(sha1) = &config.sha1 {
+        println!("# Config SHA-1: {}", sha1);
+    }
+    print!("{}", toml::to_string_pretty(&settings)?);
+    Ok(())
 }
 
-// Binance Futures structures
-#[derive(Debug, Deserialize)]
-struct BinanceFuturesExchangeInfo {
-    symbols: Vec<BinanceFuturesSymbol>,
+/// Refreshes twice from the same recorded responses into a scratch database
+/// and fails if the second run still changes anything, which would point at
+/// nondeterministic parsing or formatting.
+async fn verify_idempotent(cli: &Cli, config: &Config) -> Result<()> {
+    if cli.offline.is_none() && cli.replay_rows.is_none() {
+        bail!("--verify-idempotent needs fixtures, pass --offline or --replay-rows");
+    }
+    let mut conn = db::open_in_memory()?;
+    println!("Verifying idempotence, first run:");
+    refresh(cli, config, &mut conn).await?.check()?;
+    let timestamps = db::distinct_timestamps(&conn)?;
+    if timestamps > 1 {
+        bail!("The first run stamped its rows with {} different timestamps", timestamps);
+    }
+    println!("Second run:");
+    let second = refresh(cli, config, &mut conn).await?;
+    second.check()?;
+    if !second.changed.is_empty() {
+        for row in &second.changed {
+            eprintln!("Error: Changed on the second run: {}", row);
+        }
+        bail!("Not idempotent: {} rows changed on an identical second run", second.changed.len());
+    }
+    println!("Idempotent: the second run changed nothing");
+    Ok(())
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct BinanceFuturesSymbol {
-    symbol: String,
-    baseAsset: String,
-    quoteAsset: String,
-    filters: Vec<BinanceFilter>,
+/// A one-shot refresh by default; `--daemon` keeps refreshing and `--serve`
+/// serves the data, both in one process when combined. Serving alone only
+/// reads the database.
+async fn run(cli: &Cli, config: &Config) -> Result<()> {
+    let (changes, _) = broadcast::channel(16);
+    match (cli.serve, cli.daemon) {
+        (None, false) => {
+            refresh(cli, config, &mut db::open()?).await?.check()?;
+            println!("Data saved successfully!");
+            Ok(())
+        }
+        (None, true) => daemon(cli, config, &mut db::open()?, &changes).await,
+        (Some(addr), false) => server::serve(addr, config, changes).await,
+        (Some(addr), true) => {
+            let mut conn = db::open()?;
+            tokio::try_join!(server::serve(addr, config, changes.clone()), daemon(cli, config, &mut conn, &changes))?;
+            Ok(())
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(tag = "filterType")]
-enum BinanceFilter {
-    #[serde(rename = "PRICE_FILTER")]
-    PriceFilter { tickSize: String },
-    #[serde(rename = "LOT_SIZE")]
-    LotSize { stepSize: String },
-    #[serde(other)]
-    Other,
+/// Refreshes every `--interval` until the process exits, publishing each
+/// run's changes. A failed run is reported and retried on the next tick.
+/// Compaction runs on its own `--compact-interval`, between refreshes.
+async fn daemon(
+    cli: &Cli,
+    config: &Config,
+    conn: &mut Connection,
+    changes: &broadcast::Sender<server::Changes>,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(cli.interval);
+    let mut compaction = cli
+        .compact_interval
+        .map(|period| tokio::time::interval_at(tokio::time::Instant::now() + period, period));
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => match refresh(cli, config, conn).await {
+                Ok(refresh) => {
+                    if !refresh.changed.is_empty() {
+                        // No receivers just means nobody is connected
+                        let _ = changes.send(Arc::new(refresh.changed.clone()));
+                    }
+                    if let Err(err) = refresh.check() {
+                        eprintln!("Error: {:#}", err);
+                    }
+                }
+                Err(err) => eprintln!("Error: Refresh failed: {:#}", err),
+            },
+            _ = next_tick(&mut compaction) => {
+                if let Err(err) = compact(cli, conn) {
+                    eprintln!("Error: Compaction failed: {:#}", err);
+                }
+            }
+        }
+    }
 }
 
-// OKX structures
-#[derive(Debug, Deserialize)]
-struct OkxResponse {
-    data: Vec<OkxInstrument>,
+// Never completes without an interval
+async fn next_tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct OkxInstrument {
-    inst_id: String,
-    base_ccy: String,
-    quote_ccy: String,
-    tick_sz: String,
-    lot_sz: String,
+fn compact(cli: &Cli, conn: &Connection) -> Result<()> {
+    let cutoff = cli
+        .history_retention
+        .map(|retention| chrono::Duration::from_std(retention).map(|retention| Utc::now() - retention))
+        .transpose()?;
+    let compaction = db::compact(conn, cutoff)?;
+    println!(
+        "Compaction: pruned {} history rows, reclaimed {} KiB",
+        compaction.pruned,
+        compaction.reclaimed_bytes / 1024
+    );
+    Ok(())
 }
 
-const SYMBOLS: &[&str] = &["BTCUSDT", "ETHUSDT", "SOLUSDT", "LINKUSDT", "BNBUSDT", "AVAXUSDT"];
-
-fn format_symbol(base_sym: &str, quote_sym: &str, prod_type: &str) -> String {
-    format!("{}/{}-{}", base_sym, quote_sym, prod_type)
+async fn self_check(cli: &Cli, config: &Config) -> Result<()> {
+    let client = cli.http_client();
+    let exchanges = config.enabled_exchanges()?;
+    let mut unreachable = Vec::new();
+    let mut offsets = Vec::new();
+    for (exchange, outcome) in exchanges::self_check(&client, &exchanges).await {
+        match outcome {
+            Ok(probe) => {
+                let Some(offset_ms) = probe.clock_offset_ms else {
+                    println!("{}: reachable ({} ms)", exchange.name(), probe.latency.as_millis());
+                    continue;
+                };
+                println!(
+                    "{}: reachable ({} ms), clock offset {:+} ms",
+                    exchange.name(),
+                    probe.latency.as_millis(),
+                    offset_ms
+                );
+                if offset_ms.unsigned_abs() as u128 > cli.max_clock_skew.as_millis() {
+                    eprintln!(
+                        "Warning: {} clock is {:+} ms off the local one, more than --max-clock-skew {}",
+                        exchange.name(),
+                        offset_ms,
+                        humantime::format_duration(cli.max_clock_skew)
+                    );
+                }
+                offsets.push((exchange.name(), offset_ms));
+            }
+            Err(err) => {
+                println!("{}: unreachable: {:#}", exchange.name(), err);
+                unreachable.push(exchange.name());
+            }
+        }
+    }
+    db::log_clock_offsets(&db::open()?, &offsets)?;
+    if !unreachable.is_empty() {
+        bail!("Unreachable exchanges: {}", unreachable.join(", "));
+    }
+    Ok(())
 }
 
-fn remove_trailing_zeroes(num_str: &str) -> String {
-    let num: f64 = num_str.parse().unwrap_or(0.0);
-    let s = num.to_string();
-    s
+fn migrate(dry_run: bool) -> Result<()> {
+    let pending = db::pending_migrations()?;
+    if !dry_run {
+        db::open()?;
+        println!("Applied {} migrations", pending.len());
+        return Ok(());
+    }
+    for (version, description) in &pending {
+        println!("Pending migration {}: {}", version, description);
+    }
+    if !pending.is_empty() {
+        bail!("{} pending migrations", pending.len());
+    }
+    println!("Schema is up to date");
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    println!("Fetching reference data from exchanges...");
-
-    let mut all_data = Vec::new();
-
-    // Fetch Binance data
-    all_data.extend(fetch_binance_spot().await?);
-    all_data.extend(fetch_binance_futures().await?);
-
-    // Fetch OKX data
-    all_data.extend(fetch_okx_spot().await?);
-    all_data.extend(fetch_okx_futures().await?);
-
-    println!("Fetched {} records", all_data.len());
+/// Equal ticks go to the preferred exchange (see `Config::priority`), then to
+/// the alphabetically first symbol.
+fn best_tick(config: &Config, canonical: &str) -> Result<()> {
+    let canonical = canonical.to_uppercase();
+    let rows = db::instruments_by_canonical(&db::open_read_only()?, &canonical)?;
+    let mut venues = Vec::new();
+    for row in &rows {
+        let (Some(exchange), Some(symbol), Some(tick)) =
+            (row["exchange"].as_str(), row["symbol"].as_str(), row["tick_size"].as_str())
+        else {
+            continue;
+        };
+        venues.push((parse_size(tick)?, config.priority(exchange), symbol));
+    }
+    let Some((tick, (_, exchange), symbol)) = venues.into_iter().min() else {
+        bail!("No listed instruments with canonical symbol {}", canonical);
+    };
+    println!("Best tick for {}: {} on {} {} (of {} venues)", canonical, tick, exchange, symbol, rows.len());
+    Ok(())
+}
 
-    // Save to SQLite
-    save_to_sqlite(all_data)?;
+fn export(cli: &Cli, config: &Config, conn: &Connection, since_id: i64) -> Result<()> {
+    let (mut rows, max_id) = db::history_since(conn, since_id)?;
+    // max_id still covers the filtered out rows, so they aren't read again
+    rows.retain(|row| cli.wants_product(row["product_type"].as_str().unwrap_or_default()));
+    for row in &mut rows {
+        if let Some(product_type) = row["product_type"].as_str() {
+            row["product_type"] = config.product_label(product_type).into();
+        }
+    }
+    let output = serde_json::json!({ "rows": rows, "max_id": max_id });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
 
-    println!("Data saved successfully!");
+fn query(cli: &Cli, conn: &Connection, tag: Option<String>) -> Result<()> {
+    let mut rows = db::instruments(conn, &db::InstrumentFilter { tag, ..Default::default() })?;
+    rows.retain(|row| cli.wants_product(row["product_type"].as_str().unwrap_or_default()));
+    println!("{}", serde_json::to_string_pretty(&rows)?);
     Ok(())
 }
 
-async fn fetch_binance_spot() -> Result<Vec<ReferenceData>> {
-    println!("Processing Binance SPOT...");
-    let url = "https://api.binance.com/api/v3/exchangeInfo";
-    let client = reqwest::Client::new();
-    let response: BinanceSpotExchangeInfo = client
-        .get(url)
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    let mut results = Vec::new();
-    for symbol_info in response.symbols {
-        if !SYMBOLS.contains(&symbol_info.symbol.as_str()) {
-            continue;
+/// Outcome of one refresh run.
+#[derive(Default)]
+struct Refresh {
+    /// Rows that were inserted or changed, as stored
+    changed: Vec<JsonValue>,
+    failed: Vec<&'static str>,
+    /// Outputs that couldn't be written; the others still got the data
+    failed_outputs: Vec<&'static str>,
+    /// Exchanges whose row count dropped too far since `--compare-manifest`
+    regressed: Vec<&'static str>,
+    /// Exchanges past their `--alert-stale` age
+    stale: Vec<&'static str>,
+}
+
+impl Refresh {
+    /// Fails if any exchange couldn't be fetched, regressed or is stale.
+    fn check(&self) -> Result<()> {
+        if !self.failed.is_empty() {
+            bail!("Failed exchanges: {}", self.failed.join(", "));
+        }
+        if !self.failed_outputs.is_empty() {
+            bail!("Failed outputs: {}", self.failed_outputs.join(", "));
+        }
+        if !self.regressed.is_empty() {
+            bail!("Row count regressions: {}", self.regressed.join(", "));
+        }
+        if !self.stale.is_empty() {
+            bail!("Stale exchanges: {}", self.stale.join(", "));
         }
+        Ok(())
+    }
+}
 
-        let mut tick_size = String::new();
-        let mut lot_size = String::new();
+/// Fails when an exchange would write more than `--max-rows-per-exchange`
+/// rows, naming every such exchange, unless `--force` is given.
+fn check_row_cap(cli: &Cli, data: &[model::ReferenceData]) -> Result<()> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for item in data {
+        *counts.entry(&item.exchange).or_default() += 1;
+    }
+    let over: Vec<String> = counts
+        .into_iter()
+        .filter(|&(_, count)| count > cli.max_rows_per_exchange)
+        .map(|(exchange, count)| format!("{} ({} rows)", exchange, count))
+        .collect();
+    if over.is_empty() {
+        return Ok(());
+    }
+    if cli.force {
+        eprintln!(
+            "Warning: Over --max-rows-per-exchange {}, saving anyway (--force): {}",
+            cli.max_rows_per_exchange,
+            over.join(", ")
+        );
+        return Ok(());
+    }
+    bail!(
+        "Not saving, over --max-rows-per-exchange {}: {}; pass --force to save anyway",
+        cli.max_rows_per_exchange,
+        over.join(", ")
+    );
+}
 
-        for filter in symbol_info.filters {
-            match filter {
-                BinanceFilter::PriceFilter { tickSize } => tick_size = tickSize,
-                BinanceFilter::LotSize { stepSize } => lot_size = stepSize,
-                _ => {}
+/// Fails listing the universe entries no exchange returned, e.g. typos or
+/// pairs delisted everywhere.
+fn check_universe(cli: &Cli, universe: &[String], data: &[model::ReferenceData]) -> Result<()> {
+    let found: HashSet<String> = data
+        .iter()
+        .map(|item| {
+            if cli.bases.is_empty() {
+                format!("{}{}", item.base, item.quote)
+            } else {
+                item.base.clone()
             }
-        }
-
-        results.push(ReferenceData {
-            product_type: "spot".to_string(),
-            exchange: "binance".to_string(),
-            symbol: format_symbol(&symbol_info.baseAsset, &symbol_info.quoteAsset, "SPOT"),
-            tick_size: remove_trailing_zeroes(&tick_size),
-            lot_size: remove_trailing_zeroes(&lot_size),
-        });
+        })
+        .collect();
+    let missing: Vec<&str> = universe
+        .iter()
+        .filter(|entry| !found.contains(&normalize::asset(entry)))
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        bail!("No exchange returned: {}", missing.join(", "));
     }
+    Ok(())
+}
 
-    Ok(results)
+/// The `limit` exchanges whose last fetch is the oldest, never fetched ones
+/// first and ties in config order, for `--limit-exchanges`.
+fn round_robin(conn: &Connection, mut exchanges: Vec<Exchange>, limit: usize) -> Result<Vec<Exchange>> {
+    let last_fetches = db::last_fetches(conn)?;
+    let total = exchanges.len();
+    exchanges.sort_by_key(|exchange| last_fetches.get(exchange.name()).copied());
+    exchanges.truncate(limit);
+    let names: Vec<&str> = exchanges.iter().map(|exchange| exchange.name()).collect();
+    println!("Round-robin: refreshing {} ({} of {} exchanges)", names.join(", "), exchanges.len(), total);
+    Ok(exchanges)
 }
 
-async fn fetch_binance_futures() -> Result<Vec<ReferenceData>> {
-    println!("Processing Binance PERP...");
-    let url = "https://fapi.binance.com/fapi/v1/exchangeInfo";
-    let client = reqwest::Client::new();
-    let response: BinanceFuturesExchangeInfo = client
-        .get(url)
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    let mut results = Vec::new();
-    for symbol_info in response.symbols {
-        if !SYMBOLS.contains(&symbol_info.symbol.as_str()) {
-            continue;
+/// Fetches all (or the previously failed) exchanges and stores the result.
+async fn refresh(cli: &Cli, config: &Config, conn: &mut Connection) -> Result<Refresh> {
+    let started = Instant::now();
+    let fetched_at = Utc::now();
+    let client = cli.http_client();
+    // The symbol source, webhooks, Pushgateway and alerts are internal
+    // services, never reached through --proxy
+    let internal_client = reqwest::Client::new();
+    for alias in &config.alias {
+        db::add_alias(conn, &alias.exchange, &alias.old, &alias.new)?;
+    }
+    db::set_tags(conn, &config.tags)?;
+
+    let exchanges = if cli.retry_failed {
+        let failed: Vec<Exchange> = db::failed_exchanges(conn)?
+            .iter()
+            .filter_map(|name| config.exchange(name))
+            .collect();
+        if failed.is_empty() {
+            println!("No failed exchanges to retry");
+            return Ok(Refresh::default());
+        }
+        failed
+    } else {
+        config.enabled_exchanges()?
+    };
+
+    let exchanges: Vec<Exchange> = exchanges
+        .into_iter()
+        .filter(|exchange| match config.maintenance_window(exchange.name(), fetched_at) {
+            Some(window) => {
+                println!(
+                    "Skipping {}: in maintenance until {}{}",
+                    exchange.name(),
+                    window.end,
+                    window.reason.as_deref().map(|reason| format!(" ({})", reason)).unwrap_or_default()
+                );
+                false
+            }
+            None => true,
+        })
+        .collect();
+    let exchanges = match cli.limit_exchanges {
+        Some(limit) => round_robin(conn, exchanges, limit as usize)?,
+        None => exchanges,
+    };
+
+    let symbols = match &cli.refresh_one {
+        Some(pair) => vec![pair.concat()],
+        // Matched on the base alone, or picked by volume
+        None if !cli.bases.is_empty() || cli.top_by_volume.is_some() => Vec::new(),
+        None if !cli.symbols.is_empty() => cli.symbols.clone(),
+        None if cli.symbols_from_db => {
+            let stored = db::stored_symbols(conn)?;
+            if stored.is_empty() {
+                bail!("No listed instruments in the database, run a refresh without --symbols-from-db first");
+            }
+            stored
         }
+        None => symbols::load(&config.symbols, &internal_client).await?,
+    };
+    let overrides = cli.overrides.as_deref().map(overrides::load).transpose()?;
+
+    println!("Fetching reference data from exchanges...");
 
-        let mut tick_size = String::new();
-        let mut lot_size = String::new();
+    // Kept to check the result against with --strict-symbols
+    let universe = if cli.bases.is_empty() { symbols.clone() } else { cli.bases.clone() };
+    let report = exchanges::fetch_all(cli, &client, &exchanges, symbols).await;
 
-        for filter in symbol_info.filters {
-            match filter {
-                BinanceFilter::PriceFilter { tickSize } => tick_size = tickSize,
-                BinanceFilter::LotSize { stepSize } => lot_size = stepSize,
-                _ => {}
+    let mut all_data = Vec::new();
+    let mut log_entries = Vec::new();
+    let mut failed = Vec::new();
+    let mut timings = Vec::new();
+    // Explicit subsets of the universe say nothing about the usual counts, nor
+    // about the instruments outside them
+    let full_universe = cli.refresh_one.is_none()
+        && cli.symbols.is_empty()
+        && cli.bases.is_empty()
+        && cli.top_by_volume.is_none()
+        && cli.quote_ccy.is_empty();
+    for outcome in report.outcomes {
+        let exchange = outcome.exchange;
+        let row_count = outcome.result.as_ref().map_or(0, Vec::len);
+        for (phase, duration) in [
+            ("fetch", outcome.timings.total),
+            ("request", outcome.timings.request),
+            ("parse", outcome.timings.parse),
+        ] {
+            timings.push(db::RunTiming { exchange: Some(exchange.name()), phase, duration, row_count });
+        }
+        let api_headers = if outcome.api_headers.is_empty() {
+            None
+        } else {
+            let headers: Vec<String> =
+                outcome.api_headers.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+            println!("API headers from {}: {}", exchange.name(), headers.join(", "));
+            Some(serde_json::to_string(&outcome.api_headers)?)
+        };
+        if outcome.dns_retries > 0 {
+            println!("DNS retries for {}: {}", exchange.name(), outcome.dns_retries);
+        }
+        if !outcome.skipped.is_empty() {
+            println!("Skipped symbols on {}: {}", exchange.name(), outcome.skipped.join(", "));
+        }
+        // Far fewer rows than usual means a broken response, not mass delistings
+        let result = match outcome.result {
+            Ok(data) if full_universe && data.len() < config.min_rows(exchange.name()) => Err(anyhow!(
+                "{} returned {} rows, expected at least {} (min_rows)",
+                exchange.name(),
+                data.len(),
+                config.min_rows(exchange.name())
+            )),
+            result => result,
+        };
+        match result {
+            Ok(data) => {
+                log_entries.push(db::FetchLogEntry {
+                    exchange: exchange.name(),
+                    row_count: data.len(),
+                    error: None,
+                    api_headers,
+                    dns_retries: outcome.dns_retries,
+                    skipped: outcome.skipped,
+                });
+                all_data.extend(data.into_iter().map(|item| item.with_symbol_format(cli.symbol_format)));
+            }
+            Err(err) => {
+                eprintln!("Error: {:#}", err);
+                log_entries.push(db::FetchLogEntry {
+                    exchange: exchange.name(),
+                    row_count: 0,
+                    error: Some(format!("{:#}", err)),
+                    api_headers,
+                    dns_retries: outcome.dns_retries,
+                    skipped: outcome.skipped,
+                });
+                failed.push(exchange.name());
             }
         }
+    }
 
-        results.push(ReferenceData {
-            product_type: "perp".to_string(),
-            exchange: "binance".to_string(),
-            symbol: format_symbol(&symbol_info.baseAsset, &symbol_info.quoteAsset, "PERP"),
-            tick_size: remove_trailing_zeroes(&tick_size),
-            lot_size: remove_trailing_zeroes(&lot_size),
-        });
+    println!("Fetched {} records", all_data.len());
+    if !report.unrecognized.is_empty() {
+        let unrecognized: Vec<&str> = report.unrecognized.iter().map(String::as_str).collect();
+        println!("Unrecognized in responses: {}", unrecognized.join(", "));
+    }
+    if cli.strict_symbols {
+        check_universe(cli, &universe, &all_data)?;
     }
 
-    Ok(results)
-}
+    if let Some(program) = &cli.post_process {
+        let fetched = all_data.len();
+        all_data = postprocess::run(program, &all_data)
+            .await?
+            .into_iter()
+            .map(|item| item.with_symbol_format(cli.symbol_format))
+            .collect();
+        println!("Post-processed {} records into {}", fetched, all_data.len());
+    }
 
-async fn fetch_okx_spot() -> Result<Vec<ReferenceData>> {
-    println!("Processing OKX SPOT...");
-    let url = "https://www.okx.com/api/v5/public/instruments?instType=SPOT";
-    let client = reqwest::Client::new();
-    let response: OkxResponse = client
-        .get(url)
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    let mut results = Vec::new();
-    for inst in response.data {
-        let normalized = inst.inst_id.replace("-", "");
-        if !SYMBOLS.contains(&normalized.as_str()) {
+    if let Some(overrides) = &overrides {
+        overrides::apply(&mut all_data, overrides);
+    }
+
+    if cli.validate_tick_lot_relationship {
+        let violations = validate::check_tick_lot(&all_data);
+        validate::report("tick/lot", &violations, cli.strict)?;
+    }
+    if cli.validate_min_qty {
+        let violations = validate::check_min_qty(&all_data);
+        validate::report("min_qty", &violations, cli.strict)?;
+    }
+    if cli.validate_precision {
+        let violations = validate::check_precision(&all_data);
+        validate::report("precision", &violations, cli.strict)?;
+    }
+    check_row_cap(cli, &all_data)?;
+
+    // The file exports go first, saving to SQLite consumes the rows. Labels
+    // only change the output, SQLite keeps the canonical product types.
+    let exported: Cow<[model::ReferenceData]> = if cli.product_filter.is_empty() && config.product_labels.is_empty() {
+        Cow::Borrowed(&all_data)
+    } else {
+        Cow::Owned(
+            all_data
+                .iter()
+                .filter(|row| cli.wants_product(&row.product_type))
+                .map(|row| model::ReferenceData {
+                    product_type: config.product_label(&row.product_type).to_string(),
+                    ..row.clone()
+                })
+                .collect(),
+        )
+    };
+    let mut failed_outputs = Vec::new();
+    let mut outputs = Vec::new();
+    for &format in &cli.output {
+        let Some(exporter) = format.exporter() else {
             continue;
+        };
+        let written = output::open(cli.out_file.as_deref())
+            .and_then(|mut out| exporter.write(&mut out, &exported, fetched_at));
+        let ok = match written {
+            Ok(()) => {
+                println!("Output {}: {} rows written", format.name(), exported.len());
+                true
+            }
+            Err(err) => {
+                eprintln!("Error: Output {} failed: {:#}", format.name(), err);
+                failed_outputs.push(format.name());
+                false
+            }
+        };
+        let destination = cli.out_file.as_ref().map_or("stdout".to_string(), |path| path.display().to_string());
+        outputs.push(manifest::OutputEntry { format: format.name(), destination, ok });
+    }
+
+    let (changed, run_id) = if cli.output.contains(&OutputFormat::Sqlite) {
+        let fetched: Vec<&str> = log_entries
+            .iter()
+            .filter(|entry| entry.error.is_none() && full_universe)
+            .map(|entry| entry.exchange)
+            .collect();
+        let change_set = changes::diff(&db::stored_sizes(conn)?, &all_data, &fetched);
+        if change_set.len() > 0 {
+            println!("{}", render::render(&change_set, cli.compare_format, cli.align)?);
         }
 
-        results.push(ReferenceData {
-            product_type: "spot".to_string(),
-            exchange: "okx".to_string(),
-            symbol: format_symbol(&inst.base_ccy, &inst.quote_ccy, "SPOT"),
-            tick_size: remove_trailing_zeroes(&inst.tick_sz),
-            lot_size: remove_trailing_zeroes(&inst.lot_sz),
+        // Save to SQLite
+        let batch_size = if cli.legacy {
+            None
+        } else {
+            cli.insert_batch_size.map(|size| size as usize)
+        };
+        let row_count = all_data.len();
+        let save_started = Instant::now();
+        let (changed, run_id) =
+            db::save_to_sqlite(conn, all_data, batch_size, fetched_at, &change_set.delisted, &log_entries)?;
+        timings.push(db::RunTiming {
+            exchange: None,
+            phase: "save",
+            duration: save_started.elapsed(),
+            row_count,
+        });
+        println!("Output sqlite: {} new or changed records", changed.len());
+        outputs.push(manifest::OutputEntry {
+            format: OutputFormat::Sqlite.name(),
+            destination: db::DB_PATH.to_string(),
+            ok: true,
         });
+
+        if let Some(url) = &cli.webhook_url {
+            let min_changes = cli.webhook_min_changes;
+            alerts::notify_changes(&internal_client, url, cli.webhook_format, min_changes, &change_set).await;
+        }
+        #[cfg(feature = "kafka")]
+        if let (Some(brokers), Some(topic)) = (&cli.kafka_brokers, &cli.kafka_topic) {
+            kafka::publish(brokers, topic, &changed).await;
+        }
+        (changed, run_id)
+    } else {
+        (Vec::new(), db::log_fetch_run(conn, &log_entries)?)
+    };
+    if cli.timing_report {
+        db::log_run_timings(conn, run_id, &timings)?;
     }
 
-    Ok(results)
-}
+    if cli.retry_failed {
+        for entry in log_entries.iter().filter(|entry| entry.error.is_none()) {
+            println!("Recovered: {}", entry.exchange);
+        }
+    }
 
-async fn fetch_okx_futures() -> Result<Vec<ReferenceData>> {
-    println!("Processing OKX PERP...");
-    let url = "https://www.okx.com/api/v5/public/instruments?instType=SWAP";
-    let client = reqwest::Client::new();
-    let response: OkxResponse = client
-        .get(url)
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    let mut results = Vec::new();
-    for inst in response.data {
-        let normalized = inst.inst_id.replace("-", "");
-        if !SYMBOLS.contains(&normalized.as_str()) {
-            continue;
+    if let Some(url) = &cli.pushgateway_url {
+        let pushed = metrics::push(&internal_client, url, &config.pushgateway, &log_entries, started.elapsed()).await;
+        if let Err(err) = pushed {
+            eprintln!("Warning: {:#}", err);
         }
+    }
 
-        results.push(ReferenceData {
-            product_type: "perp".to_string(),
-            exchange: "okx".to_string(),
-            symbol: format_symbol(&inst.base_ccy, &inst.quote_ccy, "SPOT"),
-            tick_size: remove_trailing_zeroes(&inst.tick_sz),
-            lot_size: remove_trailing_zeroes(&inst.lot_sz),
-        });
+    // Read before --manifest may overwrite the same file
+    let regressed = match &cli.compare_manifest {
+        Some(previous) => manifest::regressions(previous, &log_entries, cli.max_count_drop)?,
+        None => Vec::new(),
+    };
+    if let Some(path) = &cli.manifest {
+        let config_sha1 = config.sha1.as_deref();
+        manifest::write(path, run_id, fetched_at, started.elapsed(), config_sha1, &log_entries, &outputs)?;
+    }
+
+    let mut stale = Vec::new();
+    if let Some(max_age) = cli.alert_stale {
+        let enabled = config.enabled_exchanges()?;
+        let found = alerts::stale_exchanges(conn, &enabled, max_age, &config.stale, Utc::now())?;
+        if !found.is_empty() {
+            alerts::report_stale(&internal_client, &config.stale, &found).await;
+        }
+        stale = found.iter().map(|entry| entry.exchange).collect();
     }
 
-    Ok(results)
+    Ok(Refresh { changed, failed, failed_outputs, regressed, stale })
 }
 
-fn save_to_sqlite(data: Vec<ReferenceData>) -> Result<()> {
-    // Create or open the SQLite database file
-    let conn = Connection::open("crypto_refdata.db")
-        .context("Failed to open SQLite database")?;
-
-    // Create table if not exists
-    conn.execute(
-        r"CREATE TABLE IF NOT EXISTS reference_data (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            product_type TEXT NOT NULL,
-            exchange TEXT NOT NULL,
-            symbol TEXT NOT NULL,
-            tick_size TEXT NOT NULL,
-            lot_size TEXT NOT NULL,
-            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-            UNIQUE(product_type, exchange, symbol)
-        )",
-        [],
-    )?;
-
-    // Insert or update data
-    for item in data {
-        conn.execute(
-            r"INSERT INTO reference_data 
-              (product_type, exchange, symbol, tick_size, lot_size)
-              VALUES (?1, ?2, ?3, ?4, ?5)
-              ON CONFLICT(product_type, exchange, symbol) 
-              DO UPDATE SET
-                tick_size = excluded.tick_size,
-                lot_size = excluded.lot_size,
-                updated_at = CURRENT_TIMESTAMP",
-            params![
-                &item.product_type,
-                &item.exchange,
-                &item.symbol,
-                &item.tick_size,
-                &item.lot_size,
-            ],
-        )?;
-        println!("Saved: {} {} {}", item.exchange, item.product_type, item.symbol);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_run_stamps_all_its_rows_alike() {
+        let fixtures = exchanges::fixture_dir("recorded");
+        // One transaction per row, so the rows are written at different times
+        let cli = Cli::parse_from(["crypto_refdata", "--offline", &fixtures, "--insert-batch-size", "1"]);
+        let mut conn = db::open_in_memory().unwrap();
+        let run = refresh(&cli, &Config::default(), &mut conn).await.unwrap();
+        run.check().unwrap();
+        assert!(run.changed.len() > 1);
+        assert_eq!(db::distinct_timestamps(&conn).unwrap(), 1);
+        let stamps: HashSet<&str> = run.changed.iter().filter_map(|row| row["updated_at"].as_str()).collect();
+        assert_eq!(stamps.len(), 1);
     }
 
-    Ok(())
+    // --proxy is for the exchanges; the webhook is posted to directly even
+    // when the proxy is unreachable
+    #[tokio::test]
+    async fn webhooks_bypass_the_proxy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let webhook = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let read = stream.read(&mut request).await.unwrap();
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+            String::from_utf8_lossy(&request[..read]).lines().next().unwrap_or_default().to_string()
+        });
+        let fixtures = exchanges::fixture_dir("recorded");
+        let cli = Cli::parse_from([
+            "crypto_refdata",
+            "--offline",
+            &fixtures,
+            "--proxy",
+            "http://127.0.0.1:9",
+            "--webhook-url",
+            &webhook,
+        ]);
+        let mut conn = db::open_in_memory().unwrap();
+        refresh(&cli, &Config::default(), &mut conn).await.unwrap().check().unwrap();
+        let request_line = tokio::time::timeout(std::time::Duration::from_secs(5), received).await.unwrap().unwrap();
+        assert_eq!(request_line, "POST /hook HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn a_narrower_run_delists_nothing() {
+        let fixtures = exchanges::fixture_dir("recorded");
+        let mut conn = db::open_in_memory().unwrap();
+        let full = Cli::parse_from(["crypto_refdata", "--offline", &fixtures]);
+        refresh(&full, &Config::default(), &mut conn).await.unwrap().check().unwrap();
+        let listed = db::stored_sizes(&conn).unwrap();
+        assert!(listed.len() > 1);
+
+        for subset in [["--symbols", "BTCUSDT"], ["--bases", "ETH"], ["--quote-ccy", "USDC"]] {
+            let cli = Cli::parse_from([["crypto_refdata", "--offline", &fixtures].as_slice(), &subset].concat());
+            refresh(&cli, &Config::default(), &mut conn).await.unwrap().check().unwrap();
+            assert_eq!(db::stored_sizes(&conn).unwrap().len(), listed.len(), "{:?} delisted rows", subset);
+        }
+    }
 }
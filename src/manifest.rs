@@ -0,0 +1,117 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::db::FetchLogEntry;
+
+/// Where one output of the run went and whether it was written.
+#[derive(Debug, Serialize)]
+pub struct OutputEntry {
+    pub format: &'static str,
+    // A file path, or "stdout"
+    pub destination: String,
+    pub ok: bool,
+}
+
+/// Writes the `--manifest` of a finished run to `path`. The manifest is
+/// written next to it first and renamed into place, so readers never see a
+/// partial file.
+pub fn write(
+    path: &Path,
+    run_id: i64,
+    started_at: DateTime<Utc>,
+    duration: Duration,
+    config_sha1: Option<&str>,
+    entries: &[FetchLogEntry<'_>],
+    outputs: &[OutputEntry],
+) -> Result<()> {
+    let exchanges: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "exchange": entry.exchange,
+                "status": if entry.error.is_some() { "error" } else { "ok" },
+                "row_count": entry.row_count,
+                "error": entry.error,
+                "skipped_symbols": entry.skipped,
+            })
+        })
+        .collect();
+    let manifest = json!({
+        "run_id": run_id,
+        "started_at": started_at.to_rfc3339(),
+        "duration_seconds": duration.as_secs_f64(),
+        "config_sha1": config_sha1,
+        "exchanges": exchanges,
+        "outputs": outputs,
+    });
+
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+    std::fs::write(&partial, serde_json::to_vec_pretty(&manifest)?)
+        .and_then(|()| std::fs::rename(&partial, path))
+        .with_context(|| format!("Failed to write the manifest to {}", path.display()))
+}
+
+// The parts of an earlier manifest the regression check reads
+#[derive(Deserialize)]
+struct PreviousManifest {
+    exchanges: Vec<PreviousExchange>,
+}
+
+#[derive(Deserialize)]
+struct PreviousExchange {
+    exchange: String,
+    status: String,
+    row_count: usize,
+}
+
+/// Compares this run's row counts with the manifest of an earlier run at
+/// `previous` and returns the exchanges whose count dropped by more than
+/// `max_drop_percent`. Smaller drops are only warned about. Exchanges that
+/// failed in either run are left to the failed-exchange checks, and a missing
+/// manifest (e.g. on the first run) skips the check.
+pub fn regressions<'a>(
+    previous: &Path,
+    entries: &[FetchLogEntry<'a>],
+    max_drop_percent: f64,
+) -> Result<Vec<&'a str>> {
+    if !previous.exists() {
+        println!("No previous manifest at {}, skipping the regression check", previous.display());
+        return Ok(Vec::new());
+    }
+    let text = std::fs::read_to_string(previous)
+        .with_context(|| format!("Failed to read the previous manifest {}", previous.display()))?;
+    let manifest: PreviousManifest = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid previous manifest {}", previous.display()))?;
+
+    let mut regressed = Vec::new();
+    for entry in entries.iter().filter(|entry| entry.error.is_none()) {
+        let Some(before) = manifest
+            .exchanges
+            .iter()
+            .find(|before| before.exchange == entry.exchange && before.status == "ok" && before.row_count > 0)
+        else {
+            continue;
+        };
+        if entry.row_count >= before.row_count {
+            continue;
+        }
+        let drop = (before.row_count - entry.row_count) as f64 * 100.0 / before.row_count as f64;
+        let message = format!(
+            "{} returned {} rows, {:.1}% fewer than the {} of the previous run",
+            entry.exchange, entry.row_count, drop, before.row_count
+        );
+        if drop > max_drop_percent {
+            eprintln!("Error: {}", message);
+            regressed.push(entry.exchange);
+        } else {
+            eprintln!("Warning: {}", message);
+        }
+    }
+    Ok(regressed)
+}
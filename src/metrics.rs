@@ -0,0 +1,60 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::config::PushgatewayConfig;
+use crate::db::FetchLogEntry;
+
+/// Renders the run summary in the Prometheus text exposition format.
+fn render(entries: &[FetchLogEntry<'_>], duration: Duration) -> String {
+    let mut body = String::new();
+    body.push_str("# TYPE crypto_refdata_rows gauge\n");
+    for entry in entries {
+        let _ = writeln!(body, "crypto_refdata_rows{{exchange=\"{}\"}} {}", entry.exchange, entry.row_count);
+    }
+    body.push_str("# TYPE crypto_refdata_fetch_errors gauge\n");
+    for entry in entries {
+        let errors = u8::from(entry.error.is_some());
+        let _ = writeln!(body, "crypto_refdata_fetch_errors{{exchange=\"{}\"}} {}", entry.exchange, errors);
+    }
+    body.push_str("# TYPE crypto_refdata_dns_retries gauge\n");
+    for entry in entries {
+        let _ = writeln!(body, "crypto_refdata_dns_retries{{exchange=\"{}\"}} {}", entry.exchange, entry.dns_retries);
+    }
+    body.push_str("# TYPE crypto_refdata_run_duration_seconds gauge\n");
+    let _ = writeln!(body, "crypto_refdata_run_duration_seconds {}", duration.as_secs_f64());
+    body.push_str("# TYPE crypto_refdata_last_run_timestamp_seconds gauge\n");
+    let _ = writeln!(body, "crypto_refdata_last_run_timestamp_seconds {}", chrono::Utc::now().timestamp());
+    body
+}
+
+/// Replaces this job's metrics on the Pushgateway at `url`.
+pub async fn push(
+    client: &reqwest::Client,
+    url: &str,
+    labels: &PushgatewayConfig,
+    entries: &[FetchLogEntry<'_>],
+    duration: Duration,
+) -> Result<()> {
+    let mut endpoint = reqwest::Url::parse(url).with_context(|| format!("Invalid Pushgateway URL {}", url))?;
+    {
+        let mut segments = endpoint
+            .path_segments_mut()
+            .map_err(|_| anyhow!("Invalid Pushgateway URL {}", url))?;
+        segments.pop_if_empty().extend(["metrics", "job", labels.job.as_str()]);
+        if let Some(instance) = &labels.instance {
+            segments.extend(["instance", instance.as_str()]);
+        }
+    }
+
+    client
+        .put(endpoint.clone())
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(render(entries, duration))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Pushing metrics to {} failed", endpoint))?;
+    Ok(())
+}
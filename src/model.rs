@@ -0,0 +1,288 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::{self, QuoteKind};
+
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceData {
+    pub product_type: String,
+    pub exchange: String,
+    pub symbol: String,
+    // The exchange's own spelling, e.g. BTC-USDT-SWAP or tBTCUST
+    pub native_symbol: Option<String>,
+    // Upper-cased, see normalize::asset
+    pub base: String,
+    pub quote: String,
+    // Symbol with aliased stablecoin quotes mapped to a canonical stablecoin
+    // (USDC to USDT), see normalize
+    pub canonical: String,
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+    // The sizes exactly as the exchange sent them, e.g. 0.01000000
+    pub tick_size_raw: Option<String>,
+    pub lot_size_raw: Option<String>,
+    // Decimals the exchange states for prices and quantities where it sends
+    // them alongside the sizes, for --validate-precision; not stored
+    pub price_precision: Option<u32>,
+    pub qty_precision: Option<u32>,
+    // Smallest order quantity in base units, a multiple of the lot; Binance LOT_SIZE minQty
+    pub min_qty: Option<Decimal>,
+    // Base-asset quantity of one contract, derivatives only
+    pub contract_size: Option<Decimal>,
+    // lot_size * contract_size, see README
+    pub min_order_base_qty: Option<Decimal>,
+    // OKX instrument family and underlying (e.g. BTC-USDT), derivatives only
+    pub inst_family: Option<String>,
+    pub uly: Option<String>,
+    // Limit price bounds, Binance PRICE_FILTER
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    pub quote_kind: QuoteKind,
+    // Set by --overrides, which keeps the exchange-reported sizes alongside
+    pub overridden: bool,
+    pub exchange_tick_size: Option<Decimal>,
+    pub exchange_lot_size: Option<Decimal>,
+    // Default (lowest tier) fees as fractions, e.g. 0.001 for 0.1%, --with-fees
+    pub maker_fee: Option<Decimal>,
+    pub taker_fee: Option<Decimal>,
+    // Comma-separated account permissions, e.g. MARGIN,SPOT; Binance spot only
+    pub permissions: Option<String>,
+    // Highest leverage the exchange allows, derivatives only, --with-leverage
+    pub max_leverage: Option<Decimal>,
+    // Trading status as the exchange sends it, e.g. Binance TRADING, OKX live,
+    // Kraken online; custom exchanges have none
+    pub status: Option<String>,
+    // When the stored row last changed, only for rows read back from SQLite
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl ReferenceData {
+    /// Builds a row with the fields every exchange provides; the symbols and
+    /// quote classification are derived from the base and quote assets.
+    pub fn new(
+        exchange: &str,
+        product_type: &str,
+        base: &str,
+        quote: &str,
+        tick_size: Decimal,
+        lot_size: Decimal,
+    ) -> Self {
+        let (base, quote) = (normalize::asset(base), normalize::asset(quote));
+        Self {
+            product_type: product_type.to_string(),
+            exchange: exchange.to_string(),
+            symbol: format_symbol(&base, &quote, &product_type.to_uppercase()),
+            canonical: format_symbol(&base, &normalize::canonical_quote(&quote), &product_type.to_uppercase()),
+            tick_size,
+            lot_size,
+            quote_kind: normalize::quote_kind(&quote),
+            base,
+            quote,
+            ..Default::default()
+        }
+    }
+
+    /// Keeps the exchange's spelling of the tick and lot size next to the
+    /// parsed values.
+    pub fn with_raw_sizes(mut self, tick_size: &str, lot_size: &str) -> Self {
+        self.tick_size_raw = Some(tick_size.to_string());
+        self.lot_size_raw = Some(lot_size.to_string());
+        self
+    }
+
+    /// Records the exchange's own spelling of the instrument.
+    pub fn with_native_symbol(mut self, native_symbol: &str) -> Self {
+        self.native_symbol = Some(native_symbol.to_string());
+        self
+    }
+
+    pub fn is_derivative(&self) -> bool {
+        self.product_type != "spot"
+    }
+
+    /// Re-renders `symbol` from the base, quote and product type.
+    pub fn with_symbol_format(mut self, format: SymbolFormat) -> Self {
+        self.symbol = format.format(&self.base, &self.quote, &self.product_type);
+        self
+    }
+
+    /// Fills `min_order_base_qty` for derivative rows that carry a contract size.
+    pub fn with_min_order_base_qty(mut self) -> Self {
+        self.min_order_base_qty = match self.contract_size {
+            Some(contract_size) if self.is_derivative() => {
+                Some((self.lot_size * contract_size).normalize())
+            }
+            _ => None,
+        };
+        self
+    }
+}
+
+pub const SYMBOLS: &[&str] = &["BTCUSDT", "ETHUSDT", "SOLUSDT", "LINKUSDT", "BNBUSDT", "AVAXUSDT"];
+
+pub fn format_symbol(base_sym: &str, quote_sym: &str, prod_type: &str) -> String {
+    format!("{}/{}-{}", base_sym, quote_sym, prod_type)
+}
+
+/// How the `symbol` column is rendered, see `--symbol-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymbolFormat {
+    /// BTC/USDT-SPOT
+    Slash,
+    /// BTCUSDT
+    Concat,
+    /// BTC-USDT
+    Dash,
+}
+
+impl SymbolFormat {
+    pub fn format(self, base_sym: &str, quote_sym: &str, prod_type: &str) -> String {
+        match self {
+            SymbolFormat::Slash => format_symbol(base_sym, quote_sym, &prod_type.to_uppercase()),
+            SymbolFormat::Concat => format!("{}{}", base_sym, quote_sym),
+            SymbolFormat::Dash => format!("{}-{}", base_sym, quote_sym),
+        }
+    }
+}
+
+/// A product type as stored in the `product_type` column, see
+/// `--product-filter` and `product_labels`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProductType {
+    Spot,
+    Perp,
+    Future,
+    Option,
+}
+
+impl ProductType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProductType::Spot => "spot",
+            ProductType::Perp => "perp",
+            ProductType::Future => "future",
+            ProductType::Option => "option",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ProductType> {
+        ProductType::value_variants().iter().copied().find(|product_type| product_type.name() == name)
+    }
+}
+
+/// Parses a tick or lot size exactly, dropping trailing zeros. Integer sizes
+/// stay integers (OKX's `1` is stored as `1`, not `1.0`) and small ones keep
+/// every digit in plain notation (`0.00000001`, also when sent as `1e-8`), so
+/// the stored text parses back to the same value.
+pub fn parse_size(num_str: &str) -> Result<Decimal> {
+    let num: Decimal = clean_size(num_str)?
+        .parse()
+        .with_context(|| format!("Invalid size value '{}'", num_str))?;
+    let num = num.normalize();
+    debug_assert_eq!(num.to_string().parse::<Decimal>().ok(), Some(num), "{} doesn't round-trip", num_str);
+    Ok(num)
+}
+
+/// Undoes the number formatting quirks that are unambiguous, surrounding
+/// whitespace and thousands separators (`1,000.5`), and rejects the ones
+/// that aren't: a comma that can't be a thousands separator (`0,01`, or
+/// `0,001`, which is a European 0.001 rather than 1) and underscores, which
+/// `Decimal` would silently drop.
+fn clean_size(num_str: &str) -> Result<String> {
+    let value = num_str.trim();
+    if value.is_empty() {
+        bail!("Empty size value '{}'", num_str);
+    }
+    if value.contains('_') {
+        bail!("Invalid size value '{}': unexpected '_'", num_str);
+    }
+    if !value.contains(',') {
+        return Ok(value.to_string());
+    }
+    let (integer, fraction) = match value.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (value, None),
+    };
+    let groups: Vec<&str> = integer.split(',').collect();
+    let digits = |group: &str| group.bytes().all(|byte| byte.is_ascii_digit());
+    // A leading group starting with 0 is a decimal comma, never thousands
+    let thousands = (1..=3).contains(&groups[0].len())
+        && digits(groups[0])
+        && !groups[0].starts_with('0')
+        && groups[1..].iter().all(|group| group.len() == 3 && digits(group));
+    if !thousands {
+        bail!("Invalid size value '{}': ',' is not a thousands separator here", num_str);
+    }
+    let integer = groups.concat();
+    Ok(match fraction {
+        Some(fraction) => format!("{}.{}", integer, fraction),
+        None => integer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sizes are stored as their Display text, see db::row_values
+    fn stored(value: &str) -> String {
+        parse_size(value).unwrap().to_string()
+    }
+
+    #[test]
+    fn okx_sizes_store_exactly() {
+        // tickSz/lotSz of BTC-USDT, BTC-USDT-SWAP and BTC-USD-SWAP
+        assert_eq!(stored("1"), "1");
+        assert_eq!(stored("0.1"), "0.1");
+        assert_eq!(stored("0.01"), "0.01");
+        assert_eq!(stored("0.00000001"), "0.00000001");
+        assert_eq!(stored("1e-8"), "0.00000001");
+        assert_eq!(stored("0.10000000"), "0.1");
+        for value in ["1", "0.1", "0.00000001", "1e-8"] {
+            assert_eq!(stored(&stored(value)), stored(value), "{} doesn't round-trip", value);
+        }
+    }
+
+    #[test]
+    fn thousands_separators_are_dropped() {
+        assert_eq!(parse_size("1,000").unwrap(), Decimal::new(1000, 0));
+        assert_eq!(parse_size("1,000.5").unwrap(), Decimal::new(10005, 1));
+    }
+
+    #[test]
+    fn decimal_commas_are_rejected() {
+        assert!(parse_size("0,001").is_err());
+        assert!(parse_size("0,1").is_err());
+        assert!(parse_size("01,000").is_err());
+    }
+
+    #[test]
+    fn underscores_are_rejected() {
+        assert!(parse_size("1_000").is_err());
+    }
+
+    // Units or other text after the number, e.g. `0.01 USDT`
+    #[test]
+    fn trailing_text_is_rejected() {
+        for value in ["0.01 USDT", "0.01USDT", "1e-8 BTC", "0.1%"] {
+            let err = parse_size(value).unwrap_err();
+            assert!(err.to_string().starts_with(&format!("Invalid size value '{}'", value)), "{:#}", err);
+        }
+    }
+
+    #[test]
+    fn whitespace_is_trimmed() {
+        assert_eq!(parse_size(" 0.01\t").unwrap(), Decimal::new(1, 2));
+    }
+
+    #[test]
+    fn empty_values_are_rejected() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("   ").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! Central tables for classifying and normalizing assets across exchanges.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Result};
+
+/// What kind of asset an instrument is quoted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuoteKind {
+    Stablecoin,
+    Fiat,
+    #[default]
+    Crypto,
+}
+
+impl QuoteKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuoteKind::Stablecoin => "stablecoin",
+            QuoteKind::Fiat => "fiat",
+            QuoteKind::Crypto => "crypto",
+        }
+    }
+}
+
+const STABLECOINS: &[&str] = &[
+    "USDT", "USDC", "DAI", "FDUSD", "TUSD", "BUSD", "USDP", "PYUSD", "USDE", "USDD", "EURC", "EURI",
+];
+
+const FIAT: &[&str] = &[
+    "USD", "EUR", "GBP", "JPY", "KRW", "TRY", "BRL", "AUD", "CAD", "CHF", "ZAR", "UAH", "PLN", "RON",
+    "ARS", "MXN", "IDR", "NGN", "AED", "SGD", "HKD",
+];
+
+/// Classifies a quote asset. Anything that isn't a known stablecoin or fiat
+/// currency is treated as crypto.
+pub fn quote_kind(quote: &str) -> QuoteKind {
+    let quote = quote.to_ascii_uppercase();
+    if STABLECOINS.contains(&quote.as_str()) {
+        QuoteKind::Stablecoin
+    } else if FIAT.contains(&quote.as_str()) {
+        QuoteKind::Fiat
+    } else {
+        QuoteKind::Crypto
+    }
+}
+
+/// The stored spelling of an asset. Most exchanges report upper case, some
+/// (e.g. Bitfinex) lower or mixed case; storing one form keeps joins and the
+/// instrument key case-insensitive in effect.
+pub fn asset(name: &str) -> String {
+    name.trim().to_ascii_uppercase()
+}
+
+/// Stablecoins and the canonical stablecoin of their peg. Markets quoted in
+/// any of them share the canonical stablecoin in their canonical symbol, so
+/// e.g. BTC/USDC, BTC/FDUSD and BTC/USDT all match as `BTC/USDT`, while
+/// BTC/USD, quoted in the fiat currency itself, stays apart.
+const DEFAULT_STABLE_ALIASES: &[(&str, &str)] = &[
+    ("USDC", "USDT"),
+    ("DAI", "USDT"),
+    ("FDUSD", "USDT"),
+    ("TUSD", "USDT"),
+    ("BUSD", "USDT"),
+    ("USDP", "USDT"),
+    ("PYUSD", "USDT"),
+    ("USDE", "USDT"),
+    ("USDD", "USDT"),
+    ("EURI", "EURC"),
+];
+
+static STABLE_ALIASES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Sets up the stable aliases once per process: the defaults, overridden or
+/// extended by the config's `[stable_aliases]` entries.
+pub fn init(configured: &HashMap<String, String>) -> Result<()> {
+    let _ = STABLE_ALIASES.set(stable_aliases(configured)?);
+    Ok(())
+}
+
+// The defaults with `configured` on top, refusing chains such as USDC -> USDT
+// next to USDT -> DAI, which would match the two apart
+fn stable_aliases(configured: &HashMap<String, String>) -> Result<HashMap<String, String>> {
+    let mut aliases: HashMap<String, String> = DEFAULT_STABLE_ALIASES
+        .iter()
+        .map(|&(alias, canonical)| (alias.to_string(), canonical.to_string()))
+        .collect();
+    aliases.extend(configured.iter().map(|(alias, canonical)| (asset(alias), asset(canonical))));
+    aliases.retain(|alias, canonical| alias != canonical);
+    for (alias, canonical) in &aliases {
+        if let Some(next) = aliases.get(canonical) {
+            bail!(
+                "Stable alias {} -> {} leads on to {} -> {}, alias them to one stablecoin",
+                alias,
+                canonical,
+                canonical,
+                next
+            );
+        }
+    }
+    Ok(aliases)
+}
+
+/// The quote asset used for cross-exchange matching: the canonical
+/// stablecoin for aliased stablecoins, the asset itself otherwise.
+pub fn canonical_quote(quote: &str) -> String {
+    let aliases =
+        STABLE_ALIASES.get_or_init(|| stable_aliases(&HashMap::new()).expect("the defaults chain no aliases"));
+    canonical_quote_in(aliases, quote)
+}
+
+fn canonical_quote_in(aliases: &HashMap<String, String>, quote: &str) -> String {
+    let quote = asset(quote);
+    aliases.get(&quote).cloned().unwrap_or(quote)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::model::ReferenceData;
+
+    fn canonical(configured: &[(&str, &str)], quote: &str) -> String {
+        let configured = configured.iter().map(|&(alias, canonical)| (alias.to_string(), canonical.to_string()));
+        canonical_quote_in(&stable_aliases(&configured.collect()).unwrap(), quote)
+    }
+
+    #[test]
+    fn dollar_stablecoins_match_as_usdt_but_not_as_usd() {
+        for quote in ["USDT", "USDC", "FDUSD", "TUSD", "BUSD", "DAI", "usdc"] {
+            assert_eq!(canonical(&[], quote), "USDT", "{}", quote);
+        }
+        assert_eq!(canonical(&[], "USD"), "USD");
+        assert_eq!(canonical(&[], "EURI"), "EURC");
+        assert_eq!(canonical(&[], "EUR"), "EUR");
+        assert_eq!(canonical(&[], "BTC"), "BTC");
+    }
+
+    #[test]
+    fn configured_aliases_override_and_extend_the_defaults() {
+        let configured = [("USDC", "USDC"), ("usdx", "usdt")];
+        assert_eq!(canonical(&configured, "USDC"), "USDC");
+        assert_eq!(canonical(&configured, "USDX"), "USDT");
+        assert_eq!(canonical(&configured, "FDUSD"), "USDT");
+    }
+
+    #[test]
+    fn chained_aliases_are_refused() {
+        let configured = HashMap::from([("USDT".to_string(), "DAI".to_string())]);
+        assert!(stable_aliases(&configured).is_err());
+    }
+
+    #[test]
+    fn the_raw_quote_is_kept() {
+        let item = ReferenceData::new("binance", "spot", "BTC", "FDUSD", Decimal::new(1, 2), Decimal::new(1, 5));
+        assert_eq!((item.quote.as_str(), item.canonical.as_str()), ("FDUSD", "BTC/USDT-SPOT"));
+    }
+}
@@ -0,0 +1,302 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+use crate::display;
+use crate::model::ReferenceData;
+
+/// Where a run's rows go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Upsert into the SQLite database
+    Sqlite,
+    /// InfluxDB line protocol
+    Influx,
+    /// Comma-separated values with a header row
+    Csv,
+    /// A JSON array with one object per row
+    Json,
+}
+
+impl OutputFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Sqlite => "sqlite",
+            OutputFormat::Influx => "influx",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+        }
+    }
+
+    /// The file export of this format; SQLite is stored by `db` instead.
+    pub fn exporter(self) -> Option<Box<dyn Export>> {
+        match self {
+            OutputFormat::Sqlite => None,
+            OutputFormat::Influx => Some(Box::new(Influx)),
+            OutputFormat::Csv => Some(Box::new(Csv)),
+            OutputFormat::Json => Some(Box::new(Json)),
+        }
+    }
+}
+
+/// A file format a run's rows can be written in. Rows are written one at a
+/// time, so that an export can be streamed while it is read.
+pub trait Export {
+    /// Writes what comes before the first row, e.g. a header.
+    fn begin(&self, _out: &mut dyn Write) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes the row numbered `index` from 0.
+    fn row(&self, out: &mut dyn Write, index: usize, item: &ReferenceData, updated_at: DateTime<Utc>) -> Result<()>;
+
+    /// Writes what comes after the last of `count` rows, and flushes.
+    fn end(&self, out: &mut dyn Write, _count: usize) -> Result<()> {
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Writes all of `data`.
+    fn write(&self, out: &mut dyn Write, data: &[ReferenceData], updated_at: DateTime<Utc>) -> Result<()> {
+        self.begin(out)?;
+        for (index, item) in data.iter().enumerate() {
+            self.row(out, index, item, updated_at)?;
+        }
+        self.end(out, data.len())
+    }
+}
+
+/// Opens `path` for writing, or stdout when there is none.
+pub fn open(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(io::stdout())),
+    })
+}
+
+// Tag keys and values escape commas, equals signs and spaces
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// One `reference_data` point per row, stamped with `updated_at`.
+pub struct Influx;
+
+impl Export for Influx {
+    fn row(&self, out: &mut dyn Write, _index: usize, item: &ReferenceData, updated_at: DateTime<Utc>) -> Result<()> {
+        let timestamp = updated_at.timestamp_nanos_opt().context("Timestamp out of range")?;
+        let mut fields = vec![
+            format!("tick_size={}", item.tick_size),
+            format!("lot_size={}", item.lot_size),
+        ];
+        let optional: [(&str, Option<Decimal>); 8] = [
+            ("min_qty", item.min_qty),
+            ("contract_size", item.contract_size),
+            ("min_order_base_qty", item.min_order_base_qty),
+            ("min_price", item.min_price),
+            ("max_price", item.max_price),
+            ("maker_fee", item.maker_fee),
+            ("taker_fee", item.taker_fee),
+            ("max_leverage", item.max_leverage),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                fields.push(format!("{}={}", name, value));
+            }
+        }
+
+        writeln!(
+            out,
+            "reference_data,exchange={},product_type={},symbol={} {} {}",
+            escape_tag(&item.exchange),
+            escape_tag(&item.product_type),
+            escape_tag(&item.symbol),
+            fields.join(","),
+            timestamp,
+        )?;
+        Ok(())
+    }
+}
+
+// Columns of the CSV and JSON exports, in `export_fields` order
+const EXPORT_COLUMNS: &[&str] = &[
+    "exchange",
+    "product_type",
+    "symbol",
+    "native_symbol",
+    "base",
+    "quote",
+    "canonical",
+    "tick_size",
+    "lot_size",
+    "tick_size_raw",
+    "lot_size_raw",
+    "min_qty",
+    "contract_size",
+    "min_order_base_qty",
+    "min_price",
+    "max_price",
+    "quote_kind",
+    "maker_fee",
+    "taker_fee",
+    "permissions",
+    "max_leverage",
+    "status",
+    "updated_at",
+];
+
+// Values as stored in SQLite, decimals as text to keep their precision;
+// `updated_at` is the run's unless the row was read back from SQLite
+fn export_fields(item: &ReferenceData, updated_at: DateTime<Utc>) -> Vec<Option<String>> {
+    fn decimal(value: Option<Decimal>) -> Option<String> {
+        value.map(|value| value.to_string())
+    }
+
+    vec![
+        Some(item.exchange.clone()),
+        Some(item.product_type.clone()),
+        Some(item.symbol.clone()),
+        item.native_symbol.clone(),
+        Some(item.base.clone()),
+        Some(item.quote.clone()),
+        Some(item.canonical.clone()),
+        Some(item.tick_size.to_string()),
+        Some(item.lot_size.to_string()),
+        item.tick_size_raw.clone(),
+        item.lot_size_raw.clone(),
+        decimal(item.min_qty),
+        decimal(item.contract_size),
+        decimal(item.min_order_base_qty),
+        decimal(item.min_price),
+        decimal(item.max_price),
+        Some(item.quote_kind.as_str().to_string()),
+        decimal(item.maker_fee),
+        decimal(item.taker_fee),
+        item.permissions.clone(),
+        decimal(item.max_leverage),
+        item.status.clone(),
+        Some(item.updated_at.unwrap_or(updated_at).to_rfc3339()),
+    ]
+}
+
+// Quotes a field when it contains a separator, quote or newline
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A header and one line per row, missing values left empty. Like the JSON
+/// export, except that tick and lot sizes are shown at their display scale
+/// (see `display`).
+pub struct Csv;
+
+impl Export for Csv {
+    fn begin(&self, out: &mut dyn Write) -> Result<()> {
+        writeln!(out, "{}", EXPORT_COLUMNS.join(","))?;
+        Ok(())
+    }
+
+    fn row(&self, out: &mut dyn Write, _index: usize, item: &ReferenceData, updated_at: DateTime<Utc>) -> Result<()> {
+        let column = |name| EXPORT_COLUMNS.iter().position(|column| *column == name);
+        let mut fields = export_fields(item, updated_at);
+        if let Some(index) = column("tick_size") {
+            fields[index] = Some(display::size(item.tick_size, &item.quote));
+        }
+        if let Some(index) = column("lot_size") {
+            fields[index] = Some(display::size(item.lot_size, &item.quote));
+        }
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| escape_csv(field.as_deref().unwrap_or_default()))
+            .collect();
+        writeln!(out, "{}", fields.join(","))?;
+        Ok(())
+    }
+}
+
+/// A pretty-printed JSON array of objects, missing values as `null`.
+pub struct Json;
+
+impl Export for Json {
+    fn begin(&self, out: &mut dyn Write) -> Result<()> {
+        write!(out, "[")?;
+        Ok(())
+    }
+
+    fn row(&self, out: &mut dyn Write, index: usize, item: &ReferenceData, updated_at: DateTime<Utc>) -> Result<()> {
+        let fields = EXPORT_COLUMNS
+            .iter()
+            .zip(export_fields(item, updated_at))
+            .map(|(column, value)| (column.to_string(), value.map_or(JsonValue::Null, JsonValue::String)));
+        let object = serde_json::to_string_pretty(&JsonValue::Object(fields.collect()))?;
+        // Indented as an element of the array, strings never span lines
+        writeln!(out, "{}", if index == 0 { "" } else { "," })?;
+        for (i, line) in object.lines().enumerate() {
+            write!(out, "{}  {}", if i == 0 { "" } else { "\n" }, line)?;
+        }
+        Ok(())
+    }
+
+    fn end(&self, out: &mut dyn Write, count: usize) -> Result<()> {
+        writeln!(out, "{}]", if count == 0 { "" } else { "\n" })?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Row by row, the JSON export is the pretty-printed array it used to be
+    #[test]
+    fn streamed_json_is_a_pretty_array() {
+        let row = |base| ReferenceData::new("okx", "spot", base, "USDT", Decimal::new(1, 2), Decimal::new(1, 4));
+        let updated_at = Utc::now();
+        for data in [vec![], vec![row("BTC")], vec![row("BTC"), row("ETH")]] {
+            let mut out = Vec::new();
+            Json.write(&mut out, &data, updated_at).unwrap();
+            let rows: Vec<JsonValue> = serde_json::from_slice(&out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), serde_json::to_string_pretty(&rows).unwrap() + "\n");
+            assert_eq!(rows.len(), data.len());
+        }
+    }
+
+    // Tick and lot both take the quote's scale: 2 for USDT, 8 for BTC
+    #[test]
+    fn csv_sizes_use_the_quote_scale() {
+        display::init(&Default::default());
+        let sizes = |base, quote| {
+            let item = ReferenceData::new("okx", "spot", base, quote, Decimal::new(1, 1), Decimal::new(1, 3));
+            let mut out = Vec::new();
+            Csv.row(&mut out, 0, &item, Utc::now()).unwrap();
+            let line = String::from_utf8(out).unwrap();
+            let fields: Vec<&str> = line.trim_end().split(',').collect();
+            let column = |name| fields[EXPORT_COLUMNS.iter().position(|column| *column == name).unwrap()];
+            (column("tick_size").to_string(), column("lot_size").to_string())
+        };
+        assert_eq!(sizes("BTC", "USDT"), ("0.10".to_string(), "0.001".to_string()));
+        assert_eq!(sizes("ETH", "BTC"), ("0.10000000".to_string(), "0.00100000".to_string()));
+        assert_eq!(sizes("USDT", "TRY"), ("0.1".to_string(), "0.001".to_string()));
+    }
+}
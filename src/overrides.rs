@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::model::{parse_size, ReferenceData};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OverridesFile {
+    #[serde(rename = "override")]
+    overrides: Vec<OverrideEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OverrideEntry {
+    exchange: String,
+    product_type: String,
+    symbol: String,
+    #[serde(default)]
+    tick_size: Option<String>,
+    #[serde(default)]
+    lot_size: Option<String>,
+}
+
+/// Desk-mandated sizes replacing what the exchange reports for one instrument.
+#[derive(Debug)]
+pub struct Override {
+    pub exchange: String,
+    pub product_type: String,
+    pub symbol: String,
+    pub tick_size: Option<Decimal>,
+    pub lot_size: Option<Decimal>,
+}
+
+/// Reads the `--overrides` TOML file.
+pub fn load(path: &Path) -> Result<Vec<Override>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read overrides {}", path.display()))?;
+    let file: OverridesFile =
+        toml::from_str(&text).with_context(|| format!("Invalid overrides {}", path.display()))?;
+    file.overrides
+        .into_iter()
+        .map(|entry| {
+            let parse = |value: Option<String>| value.as_deref().map(parse_size).transpose();
+            Ok(Override {
+                tick_size: parse(entry.tick_size)
+                    .with_context(|| format!("Invalid override for {}", entry.symbol))?,
+                lot_size: parse(entry.lot_size)
+                    .with_context(|| format!("Invalid override for {}", entry.symbol))?,
+                exchange: entry.exchange,
+                product_type: entry.product_type,
+                symbol: entry.symbol,
+            })
+        })
+        .collect()
+}
+
+/// Replaces the sizes of matching rows, keeping the exchange-reported values
+/// in the `exchange_*` shadow fields, and logs every override applied.
+pub fn apply(data: &mut [ReferenceData], overrides: &[Override]) {
+    for item in data.iter_mut() {
+        let Some(entry) = overrides.iter().find(|entry| {
+            entry.exchange == item.exchange && entry.product_type == item.product_type && entry.symbol == item.symbol
+        }) else {
+            continue;
+        };
+
+        let tick_size = entry.tick_size.unwrap_or(item.tick_size);
+        let lot_size = entry.lot_size.unwrap_or(item.lot_size);
+        println!(
+            "Override: {} {} {} tick {} -> {}, lot {} -> {}",
+            item.exchange, item.product_type, item.symbol, item.tick_size, tick_size, item.lot_size, lot_size
+        );
+        item.exchange_tick_size = Some(item.tick_size);
+        item.exchange_lot_size = Some(item.lot_size);
+        item.tick_size = tick_size;
+        item.lot_size = lot_size;
+        item.overridden = true;
+        if item.min_order_base_qty.is_some() {
+            item.min_order_base_qty = item.contract_size.map(|contract_size| (lot_size * contract_size).normalize());
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{bail, Context, Result};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::exchanges::replay;
+use crate::model::ReferenceData;
+
+/// Pipes the fetched rows through the `--post-process` program: the rows go to
+/// its stdin as a JSON array (see `replay::to_json`) and whatever it prints on
+/// stdout, in the same shape, replaces them. A program that fails or prints
+/// something that doesn't parse fails the run.
+pub async fn run(program: &Path, data: &[ReferenceData]) -> Result<Vec<ReferenceData>> {
+    let input = replay::to_json(data)?;
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start post-process program {}", program.display()))?;
+
+    // Written concurrently with reading stdout, a large input would otherwise
+    // fill both pipes and deadlock
+    let mut stdin = child.stdin.take().context("Post-process stdin not captured")?;
+    let writer = tokio::spawn(async move {
+        stdin.write_all(&input).await?;
+        stdin.shutdown().await
+    });
+    let output = child.wait_with_output().await?;
+    writer
+        .await?
+        .with_context(|| format!("Failed to write the rows to {}", program.display()))?;
+
+    if !output.status.success() {
+        bail!("Post-process program {} failed with {}", program.display(), output.status);
+    }
+    replay::from_json(&output.stdout)
+        .with_context(|| format!("Invalid rows from post-process program {}", program.display()))
+}
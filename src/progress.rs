@@ -0,0 +1,133 @@
+//! Optional progress bars for interactive runs (`--progress`). Output that
+//! may be printed while they are shown goes through this module so that it
+//! ends up above the bars instead of tearing them.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use crate::model::ReferenceData;
+
+struct Bars {
+    multi: MultiProgress,
+    total: Mutex<Option<Total>>,
+    // Bars of the last fetch, removed when the next one starts (--daemon)
+    shown: Mutex<Vec<ProgressBar>>,
+}
+
+// Exchanges completed in the current fetch, and the symbols they returned
+struct Total {
+    bar: ProgressBar,
+    symbols: usize,
+}
+
+static BARS: OnceLock<Bars> = OnceLock::new();
+
+/// Turns the bars on, unless stderr (where they are drawn) isn't a terminal.
+pub fn init(enabled: bool) {
+    if enabled && io::stderr().is_terminal() {
+        let _ = BARS.set(Bars {
+            multi: MultiProgress::new(),
+            total: Mutex::new(None),
+            shown: Mutex::new(Vec::new()),
+        });
+    }
+}
+
+/// Prints a status line, above the bars when they are shown.
+pub fn println(line: impl AsRef<str>) {
+    match BARS.get() {
+        Some(bars) => {
+            let _ = bars.multi.println(line);
+        }
+        None => println!("{}", line.as_ref()),
+    }
+}
+
+/// Starts the total bar of a fetch of `exchanges` exchanges.
+pub fn start(exchanges: usize) {
+    let Some(bars) = BARS.get() else {
+        return;
+    };
+    for bar in bars.shown.lock().unwrap().drain(..) {
+        bars.multi.remove(&bar);
+    }
+    let bar = bars.multi.add(ProgressBar::new(exchanges as u64));
+    bars.shown.lock().unwrap().push(bar.clone());
+    bar.set_style(
+        ProgressStyle::with_template("{bar:30} {pos}/{len} exchanges, {msg}")
+            .expect("valid template"),
+    );
+    bar.set_message("0 symbols");
+    if let Some(previous) = bars.total.lock().unwrap().replace(Total { bar, symbols: 0 }) {
+        previous.bar.finish();
+    }
+}
+
+/// Finishes the total bar once all exchanges are done.
+pub fn finish() {
+    if let Some(total) = BARS.get().and_then(|bars| bars.total.lock().unwrap().take()) {
+        total.bar.finish();
+        // The finished bars stay on screen, continue below them
+        eprintln!();
+    }
+}
+
+/// The spinner of one exchange while it is being fetched.
+pub struct ExchangeProgress {
+    name: &'static str,
+    bar: Option<ProgressBar>,
+}
+
+impl ExchangeProgress {
+    pub fn start(name: &'static str) -> Self {
+        let bar = BARS.get().map(|bars| {
+            let bar = bars.multi.add(ProgressBar::new_spinner());
+            bars.shown.lock().unwrap().push(bar.clone());
+            bar.set_message(format!("{}: fetching", name));
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        });
+        Self { name, bar }
+    }
+
+    /// Shows the outcome and counts the exchange and its symbols in the total.
+    pub fn finish(self, result: &anyhow::Result<Vec<ReferenceData>>) {
+        let (Some(bar), Some(bars)) = (self.bar, BARS.get()) else {
+            return;
+        };
+        let symbols = match result {
+            Ok(rows) => {
+                bar.finish_with_message(format!("{}: {} symbols", self.name, rows.len()));
+                rows.len()
+            }
+            Err(_) => {
+                bar.finish_with_message(format!("{}: failed", self.name));
+                0
+            }
+        };
+        if let Some(total) = bars.total.lock().unwrap().as_mut() {
+            total.symbols += symbols;
+            total.bar.set_message(format!("{} symbols", total.symbols));
+            total.bar.inc(1);
+        }
+    }
+}
+
+/// Writer for log output (`tracing`) that hides the bars while writing.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match BARS.get() {
+            Some(bars) => bars.multi.suspend(|| io::stderr().write(buf)),
+            None => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
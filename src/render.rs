@@ -0,0 +1,130 @@
+//! Renders comparison results for humans (`table`), pipelines (`json`) and
+//! chat or pull requests (`markdown`), selected with `--compare-format`.
+
+use anyhow::Result;
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompareFormat {
+    /// Aligned plain-text columns
+    Table,
+    /// The structured result, pretty-printed
+    Json,
+    /// A GitHub-flavored markdown table
+    Markdown,
+}
+
+/// Tabular view of a comparison result; the JSON form is the value itself.
+pub trait Comparison: Serialize {
+    fn headers(&self) -> &'static [&'static str];
+    fn rows(&self) -> Vec<Vec<String>>;
+}
+
+/// `align` lines up the decimal points of numeric table columns.
+pub fn render(comparison: &impl Comparison, format: CompareFormat, align: bool) -> Result<String> {
+    Ok(match format {
+        CompareFormat::Table if align => table(comparison.headers(), &align_decimals(comparison.rows())),
+        CompareFormat::Table => table(comparison.headers(), &comparison.rows()),
+        CompareFormat::Json => serde_json::to_string_pretty(comparison)?,
+        CompareFormat::Markdown => markdown(comparison.headers(), &comparison.rows()),
+    })
+}
+
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+
+    let mut lines = vec![line(headers.to_vec())];
+    lines.extend(rows.iter().map(|row| line(row.iter().map(String::as_str).collect())));
+    lines.join("\n")
+}
+
+// Separates the old and new value of a changed cell
+const CHANGE_ARROW: &str = " -> ";
+
+/// Pads the numeric columns (every non-empty cell decimals, possibly an
+/// `old -> new` change) so that decimal points line up, e.g. `0.01` and
+/// `10` become `  0.01` and ` 10   `. Other columns are left alone.
+fn align_decimals(mut rows: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    let columns = rows.first().map_or(0, Vec::len);
+    for column in 0..columns {
+        let parsed: Option<Vec<Vec<(String, String)>>> = rows
+            .iter()
+            .map(|row| {
+                let cell = row.get(column)?;
+                if cell.is_empty() {
+                    return Some(Vec::new());
+                }
+                cell.split(CHANGE_ARROW)
+                    .map(|part| part.parse::<Decimal>().ok().map(|value| split_decimal(&value)))
+                    .collect()
+            })
+            .collect();
+        let Some(parsed) = parsed else {
+            continue;
+        };
+
+        // Widest integer and fraction part per position within a cell
+        let mut widths: Vec<(usize, usize)> = Vec::new();
+        for parts in &parsed {
+            for (position, (integer, fraction)) in parts.iter().enumerate() {
+                if widths.len() <= position {
+                    widths.push((0, 0));
+                }
+                widths[position].0 = widths[position].0.max(integer.len());
+                widths[position].1 = widths[position].1.max(fraction.len());
+            }
+        }
+        for (row, parts) in rows.iter_mut().zip(&parsed) {
+            if parts.is_empty() {
+                continue;
+            }
+            let aligned: Vec<String> = parts
+                .iter()
+                .zip(&widths)
+                .map(|((integer, fraction), &(integer_width, fraction_width))| {
+                    let point = if fraction.is_empty() { " " } else { "." };
+                    let point = if fraction_width == 0 { "" } else { point };
+                    format!("{:>integer_width$}{}{:<fraction_width$}", integer, point, fraction)
+                })
+                .collect();
+            row[column] = aligned.join(CHANGE_ARROW);
+        }
+    }
+    rows
+}
+
+// e.g. 12.50 -> ("12", "5"), trailing zeros dropped
+fn split_decimal(value: &Decimal) -> (String, String) {
+    let value = value.normalize().to_string();
+    match value.split_once('.') {
+        Some((integer, fraction)) => (integer.to_string(), fraction.to_string()),
+        None => (value, String::new()),
+    }
+}
+
+fn markdown(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+    let mut lines = vec![
+        line(headers.iter().map(|header| header.to_string()).collect()),
+        line(headers.iter().map(|_| "---".to_string()).collect()),
+    ];
+    // A pipe inside a cell would end it
+    lines.extend(rows.iter().map(|row| line(row.iter().map(|cell| cell.replace('|', "\\|")).collect())));
+    lines.join("\n")
+}
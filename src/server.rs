@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use chrono::Utc;
+use clap::ValueEnum;
+use serde_json::{json, Value as JsonValue};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::config::Config;
+use crate::db::{self, InstrumentFilter};
+use crate::model::{ProductType, ReferenceData};
+use crate::output::OutputFormat;
+
+/// Rows that a refresh inserted or changed, as stored.
+pub type Changes = Arc<Vec<JsonValue>>;
+
+#[derive(Clone)]
+struct AppState {
+    changes: broadcast::Sender<Changes>,
+    // How the exports spell each stored product type (`product_labels`)
+    product_labels: Arc<HashMap<String, String>>,
+}
+
+/// Serves the stored reference data over HTTP until the process exits.
+/// Refreshes running in the same process publish their changes on `changes`.
+/// The database is only ever opened read-only and must already exist.
+pub async fn serve(addr: SocketAddr, config: &Config, changes: broadcast::Sender<Changes>) -> Result<()> {
+    db::open_read_only()?;
+    let product_labels = ProductType::value_variants()
+        .iter()
+        .map(|product_type| (product_type.name().to_string(), config.product_label(product_type.name()).to_string()))
+        .collect();
+    let app = Router::new()
+        .route("/instruments", get(instruments))
+        // Canonical symbols contain a slash, e.g. /canonical/BTC/USDT-SPOT
+        .route("/canonical/{*canonical}", get(canonical))
+        .route("/history", get(history))
+        .route("/schema", get(schema))
+        .route("/export.csv", get(|State(state), Query(filter)| export(state, filter, OutputFormat::Csv)))
+        .route("/export.json", get(|State(state), Query(filter)| export(state, filter, OutputFormat::Json)))
+        .route("/ws", get(ws))
+        .with_state(AppState { changes, product_labels: Arc::new(product_labels) });
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    println!("Serving at http://{}", addr);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+struct ServerError(anyhow::Error);
+
+impl From<anyhow::Error> for ServerError {
+    fn from(err: anyhow::Error) -> Self {
+        ServerError(err)
+    }
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        eprintln!("Error: {:#}", self.0);
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", self.0)).into_response()
+    }
+}
+
+/// Runs a query on a fresh read-only connection off the async runtime, in a
+/// read transaction: all of its statements see the same committed snapshot,
+/// never part of a refresh that is still being written.
+async fn with_db<T: Send + 'static>(
+    query: impl FnOnce(&rusqlite::Connection) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(move || {
+        let conn = db::open_read_only()?;
+        let snapshot = conn.unchecked_transaction()?;
+        query(&snapshot)
+    })
+    .await?
+}
+
+/// A file export of the `/instruments` rows like the file outputs write it,
+/// product labels included. Rows go from the cursor to the response one at a
+/// time, within one snapshot; an error after the first bytes aborts the body.
+async fn export(state: AppState, filter: InstrumentFilter, format: OutputFormat) -> Result<Response, ServerError> {
+    let (sender, mut receiver) = mpsc::channel(16);
+    let failed = sender.clone();
+    let written = with_db(move |conn| {
+        let exporter = format.exporter().expect("CSV and JSON are file exports");
+        let mut out = BufWriter::with_capacity(64 * 1024, ChannelWriter(sender));
+        let now = Utc::now();
+        let mut count = 0;
+        exporter.begin(&mut out)?;
+        db::each_instrument_row(conn, &filter, |item| {
+            let product_type = state.product_labels.get(&item.product_type).cloned();
+            let item = ReferenceData { product_type: product_type.unwrap_or(item.product_type), ..item };
+            exporter.row(&mut out, count, &item, now)?;
+            count += 1;
+            Ok(())
+        })?;
+        exporter.end(&mut out, count)
+    });
+    tokio::spawn(async move {
+        // Also fails when the client disconnects, which needs no report
+        if let Err(err) = written.await {
+            tracing::debug!(format = format.name(), "export aborted: {:#}", err);
+            let _ = failed.send(Err(io::Error::other(format!("{:#}", err)))).await;
+        }
+    });
+
+    let (content_type, extension) = match format {
+        OutputFormat::Json => ("application/json", "json"),
+        _ => ("text/csv; charset=utf-8", "csv"),
+    };
+    let disposition = format!("attachment; filename=\"reference_data.{}\"", extension);
+    let body = Body::from_stream(futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx)));
+    Ok(([(header::CONTENT_TYPE, content_type.to_string()), (header::CONTENT_DISPOSITION, disposition)], body)
+        .into_response())
+}
+
+// Hands the written chunks to the response body
+struct ChannelWriter(mpsc::Sender<io::Result<Vec<u8>>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Ok(buf.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+async fn instruments(Query(filter): Query<InstrumentFilter>) -> Result<Json<Vec<JsonValue>>, ServerError> {
+    Ok(Json(with_db(move |conn| db::instruments(conn, &filter)).await?))
+}
+
+/// Every venue's rows for one canonical instrument, grouped by exchange.
+async fn canonical(Path(canonical): Path<String>) -> Result<Response, ServerError> {
+    let canonical = canonical.to_uppercase();
+    let lookup = canonical.clone();
+    let rows = with_db(move |conn| db::instruments_by_canonical(conn, &lookup)).await?;
+    if rows.is_empty() {
+        let message = format!("No listed instruments with canonical symbol {}", canonical);
+        return Ok((StatusCode::NOT_FOUND, message).into_response());
+    }
+
+    let mut exchanges: BTreeMap<String, Vec<JsonValue>> = BTreeMap::new();
+    for row in rows {
+        let exchange = row["exchange"].as_str().unwrap_or_default().to_string();
+        exchanges.entry(exchange).or_default().push(row);
+    }
+    Ok(Json(json!({ "canonical": canonical, "exchanges": exchanges })).into_response())
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    exchange: String,
+    native_symbol: String,
+    product_type: Option<String>,
+}
+
+/// Every stored version of one instrument, across its renames.
+async fn history(Query(query): Query<HistoryQuery>) -> Result<Json<Vec<JsonValue>>, ServerError> {
+    let rows = with_db(move |conn| {
+        db::instrument_history(conn, &query.exchange, &query.native_symbol, query.product_type.as_deref())
+    })
+    .await?;
+    Ok(Json(rows))
+}
+
+/// The columns `reference_data` has right now and a JSON Schema of the rows
+/// served by `/instruments`, so clients can pick up new optional fields.
+async fn schema() -> Result<Json<JsonValue>, ServerError> {
+    let columns = with_db(|conn| db::columns(conn, "reference_data")).await?;
+    let mut properties = serde_json::Map::new();
+    for column in &columns {
+        // Rows are served with SQLite's storage classes, decimals as text
+        let kind = match column.decl_type.as_str() {
+            "INTEGER" => "integer",
+            "REAL" => "number",
+            _ => "string",
+        };
+        let kind = if column.not_null { json!(kind) } else { json!([kind, "null"]) };
+        properties.insert(column.name.clone(), json!({ "type": kind }));
+    }
+    let names: Vec<&str> = columns.iter().map(|column| column.name.as_str()).collect();
+    let listed: Vec<JsonValue> = columns
+        .iter()
+        .map(|column| json!({ "name": column.name, "type": column.decl_type, "not_null": column.not_null }))
+        .collect();
+    Ok(Json(json!({
+        "columns": listed,
+        "schema": {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "reference_data row",
+            "type": "object",
+            "properties": properties,
+            "required": names,
+        },
+    })))
+}
+
+async fn ws(upgrade: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    // Subscribe before taking the snapshot so no change falls in between
+    let changes = state.changes.subscribe();
+    upgrade.on_upgrade(move |socket| push_changes(socket, changes))
+}
+
+/// Sends a snapshot of all instruments, then every batch of changes as it is
+/// published, until the client goes away.
+async fn push_changes(mut socket: WebSocket, mut changes: broadcast::Receiver<Changes>) {
+    let snapshot = match with_db(|conn| db::instruments(conn, &InstrumentFilter::default())).await {
+        Ok(rows) => json!({ "type": "snapshot", "rows": rows }),
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            return;
+        }
+    };
+    if socket.send(Message::Text(snapshot.to_string().into())).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            received = changes.recv() => {
+                let message = match received {
+                    Ok(rows) => json!({ "type": "changes", "rows": *rows }),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        json!({ "type": "lagged", "skipped": skipped })
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                if socket.send(Message::Text(message.to_string().into())).await.is_err() {
+                    return;
+                }
+            }
+            incoming = socket.recv() => {
+                if !matches!(incoming, Some(Ok(_))) {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+
+use crate::config::SymbolSourceConfig;
+
+/// Provides the universe of symbols to collect, in exchange-native
+/// concatenated form (`BTCUSDT`).
+pub trait SymbolSource {
+    async fn symbols(&self) -> Result<Vec<String>>;
+}
+
+/// A fixed list, the built-in `SYMBOLS` unless the config overrides it.
+pub struct StaticSymbols(pub Vec<String>);
+
+impl SymbolSource for StaticSymbols {
+    async fn symbols(&self) -> Result<Vec<String>> {
+        Ok(self.0.clone())
+    }
+}
+
+/// A text file with one symbol per line. Blank lines and `#` comments are skipped.
+pub struct FileSymbols(pub PathBuf);
+
+impl SymbolSource for FileSymbols {
+    async fn symbols(&self) -> Result<Vec<String>> {
+        let text = tokio::fs::read_to_string(&self.0)
+            .await
+            .with_context(|| format!("Failed to read symbols from {}", self.0.display()))?;
+        Ok(text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// An HTTP endpoint returning a JSON array of symbols, e.g. a portfolio or
+/// risk service.
+pub struct HttpSymbols {
+    pub client: reqwest::Client,
+    pub url: String,
+}
+
+impl SymbolSource for HttpSymbols {
+    async fn symbols(&self) -> Result<Vec<String>> {
+        self.client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Request to {} failed", self.url))?
+            .json()
+            .await
+            .with_context(|| format!("Expected a JSON array of symbols from {}", self.url))
+    }
+}
+
+/// Resolves the configured universe.
+pub async fn load(config: &SymbolSourceConfig, client: &reqwest::Client) -> Result<Vec<String>> {
+    match config {
+        SymbolSourceConfig::Static { list } => StaticSymbols(list.clone()).symbols().await,
+        SymbolSourceConfig::File { path } => FileSymbols(path.clone()).symbols().await,
+        SymbolSourceConfig::Http { url } => {
+            HttpSymbols { client: client.clone(), url: url.clone() }.symbols().await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::exchanges::test_context;
+    use crate::model::ReferenceData;
+
+    #[tokio::test]
+    async fn lowercase_symbols_match_any_case() {
+        let path = std::env::temp_dir().join(format!("crypto_refdata_symbols_{}.txt", std::process::id()));
+        std::fs::write(&path, "btcusdt\nEthUsdc # mixed\n").unwrap();
+        let symbols = FileSymbols(path.clone()).symbols().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(symbols, ["btcusdt", "EthUsdc"]);
+
+        let symbols: Vec<&str> = symbols.iter().map(String::as_str).collect();
+        let ctx = test_context(&[], &symbols);
+        assert!(ctx.wants("BTC", "USDT"));
+        assert!(ctx.wants("btc", "usdt"));
+        assert!(ctx.wants("eth", "USDC"));
+        assert!(!ctx.wants("eth", "usdt"));
+    }
+
+    #[test]
+    fn lowercase_exchange_assets_are_stored_upper_case() {
+        let item = ReferenceData::new("bitfinex", "spot", "btc", " ust", Decimal::ONE, Decimal::ONE)
+            .with_native_symbol("tBTCUST");
+        assert_eq!((item.base.as_str(), item.quote.as_str()), ("BTC", "UST"));
+        assert_eq!(item.symbol, "BTC/UST-SPOT");
+        assert_eq!(item.canonical, "BTC/UST-SPOT");
+        assert_eq!(item.native_symbol.as_deref(), Some("tBTCUST"));
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use rust_decimal::Decimal;
+
+use crate::model::ReferenceData;
+
+/// A reference data row that failed a validation check.
+#[derive(Debug)]
+pub struct Violation {
+    pub exchange: String,
+    pub symbol: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.exchange, self.symbol, self.message)
+    }
+}
+
+impl Violation {
+    fn new(item: &ReferenceData, message: String) -> Self {
+        Self {
+            exchange: item.exchange.clone(),
+            symbol: item.symbol.clone(),
+            message,
+        }
+    }
+}
+
+// No listed instrument trades in steps of a million units; a lot this large is
+// a unit mix-up or a placeholder value.
+const MAX_PLAUSIBLE_LOT: Decimal = Decimal::from_parts(1_000_000, 0, 0, false, 0);
+
+/// Flags rows whose tick or lot size can't be used to form an order. Exchanges
+/// have been seen reporting a `0` tick during maintenance windows. The lot is
+/// required to be positive for spot, and no larger than `MAX_PLAUSIBLE_LOT`
+/// everywhere.
+pub fn check_tick_lot(data: &[ReferenceData]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for item in data {
+        if item.tick_size <= 0.into() {
+            violations.push(Violation::new(item, format!("tick_size {} is not positive", item.tick_size)));
+        }
+        if !item.is_derivative() && item.lot_size <= 0.into() {
+            violations.push(Violation::new(item, format!("lot_size {} is not positive", item.lot_size)));
+        }
+        if item.lot_size > MAX_PLAUSIBLE_LOT {
+            violations.push(Violation::new(
+                item,
+                format!("lot_size {} is implausibly large (over {})", item.lot_size, MAX_PLAUSIBLE_LOT),
+            ));
+        }
+    }
+    violations
+}
+
+/// Flags rows whose minimum order quantity isn't a whole number of lots,
+/// which no order could satisfy exactly. Overridden rows are checked against
+/// the exchange's own lot size, since that's what the minimum came with. The
+/// minimum is in base units, so a lot of contracts is converted first.
+pub fn check_min_qty(data: &[ReferenceData]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for item in data {
+        let Some(min_qty) = item.min_qty else {
+            continue;
+        };
+        let mut lot_size = item.exchange_lot_size.unwrap_or(item.lot_size);
+        if let Some(contract_size) = item.contract_size.filter(|_| item.is_derivative()) {
+            lot_size = (lot_size * contract_size).normalize();
+        }
+        if lot_size > 0.into() && !(min_qty % lot_size).is_zero() {
+            violations.push(Violation::new(
+                item,
+                format!("min_qty {} is not a multiple of lot_size {}", min_qty, lot_size),
+            ));
+        }
+    }
+    violations
+}
+
+/// Flags rows whose tick or lot size has more decimals than the precision the
+/// exchange states next to it, i.e. prices or quantities that precision can't
+/// express. Fewer decimals are fine: Binance documents `pricePrecision` as
+/// independent of the tick (BTCUSDT perp: tick 0.10, precision 2). Only rows
+/// that came with a precision are checked, and overridden rows use the
+/// exchange's own sizes.
+pub fn check_precision(data: &[ReferenceData]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for item in data {
+        let sizes = [
+            ("tick_size", item.exchange_tick_size.unwrap_or(item.tick_size), item.price_precision, "price"),
+            ("lot_size", item.exchange_lot_size.unwrap_or(item.lot_size), item.qty_precision, "quantity"),
+        ];
+        for (field, size, precision, kind) in sizes {
+            let Some(precision) = precision else {
+                continue;
+            };
+            let scale = size.normalize().scale();
+            if scale > precision {
+                violations.push(Violation::new(
+                    item,
+                    format!("{} {} has {} decimal(s), more than the {} precision {}", field, size, scale, kind, precision),
+                ));
+            }
+        }
+    }
+    violations
+}
+
+/// Prints `violations` and, under `--strict`, turns any of them into an error.
+pub fn report(check: &str, violations: &[Violation], strict: bool) -> Result<()> {
+    for violation in violations {
+        eprintln!("Warning: {}", violation);
+    }
+    if strict && !violations.is_empty() {
+        bail!("{} check failed with {} violation(s)", check, violations.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(tick_size: &str, lot_size: &str) -> ReferenceData {
+        ReferenceData::new("binance", "perp", "BTC", "USDT", tick_size.parse().unwrap(), lot_size.parse().unwrap())
+    }
+
+    fn with_precision(mut item: ReferenceData, price: u32, qty: u32) -> ReferenceData {
+        item.price_precision = Some(price);
+        item.qty_precision = Some(qty);
+        item
+    }
+
+    fn spot(tick_size: &str, lot_size: &str) -> ReferenceData {
+        ReferenceData { product_type: "spot".to_string(), ..row(tick_size, lot_size) }
+    }
+
+    #[test]
+    fn zero_tick_is_flagged() {
+        let violations = check_tick_lot(&[row("0", "0.001"), spot("0", "0.001")]);
+        assert_eq!(violations.len(), 2);
+        assert!(violations.iter().all(|violation| violation.message == "tick_size 0 is not positive"));
+    }
+
+    #[test]
+    fn zero_lot_is_flagged_for_spot_only() {
+        let violations = check_tick_lot(&[spot("0.01", "0"), row("0.01", "0")]);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].message, "lot_size 0 is not positive");
+    }
+
+    #[test]
+    fn implausibly_large_lot_is_flagged() {
+        assert!(check_tick_lot(&[spot("0.01", "1000000"), row("0.1", "1")]).is_empty());
+        let violations = check_tick_lot(&[spot("0.01", "10000000"), row("0.1", "1000001")]);
+        assert_eq!(violations.len(), 2);
+        assert!(violations[0].message.contains("implausibly large"));
+    }
+
+    fn with_min_qty(mut item: ReferenceData, min_qty: &str) -> ReferenceData {
+        item.min_qty = Some(min_qty.parse().unwrap());
+        item
+    }
+
+    #[test]
+    fn min_qty_multiple_of_lot_passes() {
+        let rows = [
+            with_min_qty(row("0.1", "0.001"), "0.001"),
+            with_min_qty(row("0.1", "0.001"), "0.005"),
+            // 0.3 / 0.1 doesn't terminate in binary floating point; Decimal is exact
+            with_min_qty(row("0.1", "0.1"), "0.3"),
+            with_min_qty(row("0.1", "1"), "10"),
+        ];
+        assert!(check_min_qty(&rows).is_empty());
+    }
+
+    #[test]
+    fn min_qty_off_the_lot_is_flagged() {
+        let violations = check_min_qty(&[
+            with_min_qty(row("0.1", "0.001"), "0.0015"),
+            with_min_qty(row("0.1", "0.1"), "0.25"),
+        ]);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[1].message, "min_qty 0.25 is not a multiple of lot_size 0.1");
+    }
+
+    #[test]
+    fn min_qty_is_checked_against_the_exchange_lot() {
+        let mut item = with_min_qty(row("0.1", "0.01"), "0.001");
+        item.exchange_lot_size = Some(Decimal::new(1, 3));
+        assert!(check_min_qty(&[item]).is_empty());
+        assert!(check_min_qty(&[row("0.1", "0.01")]).is_empty());
+    }
+
+    #[test]
+    fn min_qty_of_contracts_is_checked_in_base_units() {
+        // OKX ETH-USDT-SWAP: lot of 1 contract worth 0.1 ETH
+        let mut item = with_min_qty(row("0.01", "1"), "0.1");
+        item.contract_size = Some(Decimal::new(1, 1));
+        assert!(check_min_qty(std::slice::from_ref(&item)).is_empty());
+        item.min_qty = Some(Decimal::new(15, 2));
+        assert_eq!(check_min_qty(&[item]).len(), 1);
+    }
+
+    #[test]
+    fn coarser_tick_than_precision_passes() {
+        // BTCUSDT perp: tick 0.10 with pricePrecision 2, step 0.001 with quantityPrecision 3
+        assert!(check_precision(&[with_precision(row("0.10", "0.001"), 2, 3)]).is_empty());
+    }
+
+    #[test]
+    fn finer_tick_than_precision_is_flagged() {
+        let violations = check_precision(&[with_precision(row("0.001", "0.0001"), 2, 3)]);
+        assert_eq!(violations.len(), 2);
+        assert!(violations[0].message.starts_with("tick_size 0.001"));
+        assert!(violations[1].message.starts_with("lot_size 0.0001"));
+    }
+
+    #[test]
+    fn rows_without_precision_are_skipped() {
+        assert!(check_precision(&[row("0.00001", "0.00001")]).is_empty());
+    }
+
+    #[test]
+    fn overridden_rows_use_the_exchange_tick() {
+        let mut item = with_precision(row("0.001", "0.001"), 2, 3);
+        item.exchange_tick_size = Some(Decimal::new(1, 1));
+        assert!(check_precision(&[item]).is_empty());
+    }
+}
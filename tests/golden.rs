@@ -0,0 +1,179 @@
+//! Replays the recorded exchange responses in `tests/fixtures/recorded`
+//! offline and checks that the JSON output matches the checked-in
+//! `tests/golden/reference_data.json`, and that the CSV output and the SQLite
+//! database hold the same rows. Set `UPDATE_GOLDEN=1` to rewrite the golden
+//! file after an intended change.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rust_decimal::Decimal;
+use serde_json::Value;
+
+// Every column of the exports but `updated_at`, which is the time of the run
+const COLUMNS: &[&str] = &[
+    "exchange",
+    "product_type",
+    "symbol",
+    "native_symbol",
+    "base",
+    "quote",
+    "canonical",
+    "tick_size",
+    "lot_size",
+    "tick_size_raw",
+    "lot_size_raw",
+    "min_qty",
+    "contract_size",
+    "min_order_base_qty",
+    "min_price",
+    "max_price",
+    "quote_kind",
+    "maker_fee",
+    "taker_fee",
+    "permissions",
+    "max_leverage",
+    "status",
+];
+
+// Compared as numbers: the CSV pads sizes to their display scale
+const DECIMAL_COLUMNS: &[&str] = &[
+    "tick_size",
+    "lot_size",
+    "min_qty",
+    "contract_size",
+    "min_order_base_qty",
+    "min_price",
+    "max_price",
+    "maker_fee",
+    "taker_fee",
+    "max_leverage",
+];
+
+type Row = BTreeMap<String, Option<String>>;
+
+fn manifest_path(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+/// Runs a refresh from the recorded responses in a fresh directory, which
+/// receives the database, also writing `--output format` to `out.<format>`.
+fn run(format: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crypto_refdata_golden_{}_{}", std::process::id(), format));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crypto_refdata"))
+        .current_dir(&dir)
+        .arg("--offline")
+        .arg(manifest_path("tests/fixtures/recorded"))
+        .args(["--with-fees", "--with-leverage", "--output", "sqlite", "--output", format, "--out-file"])
+        .arg(format!("out.{}", format))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    dir
+}
+
+// One row's logical values, decimals normalized
+fn normalize(mut get: impl FnMut(&str) -> Option<String>) -> Row {
+    COLUMNS
+        .iter()
+        .map(|&column| {
+            let value = get(column).filter(|value| !value.is_empty());
+            let value = match value {
+                Some(value) if DECIMAL_COLUMNS.contains(&column) => {
+                    Some(value.parse::<Decimal>().unwrap().normalize().to_string())
+                }
+                value => value,
+            };
+            (column.to_string(), value)
+        })
+        .collect()
+}
+
+fn sorted(mut rows: Vec<Row>) -> Vec<Row> {
+    rows.sort_by_key(|row| (row["exchange"].clone(), row["product_type"].clone(), row["symbol"].clone()));
+    rows
+}
+
+fn json_rows(rows: &Value) -> Vec<Row> {
+    let rows = rows.as_array().unwrap().iter();
+    sorted(rows.map(|row| normalize(|column| row[column].as_str().map(str::to_string))).collect())
+}
+
+// Splits one CSV record, the fixtures hold no quotes or newlines inside a field
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+fn csv_rows(text: &str) -> Vec<Row> {
+    let mut lines = text.lines();
+    let header = csv_fields(lines.next().unwrap());
+    sorted(
+        lines
+            .map(|line| {
+                let fields = csv_fields(line);
+                assert_eq!(fields.len(), header.len(), "{}", line);
+                normalize(|column| {
+                    let index = header.iter().position(|name| name == column)?;
+                    Some(fields[index].clone())
+                })
+            })
+            .collect(),
+    )
+}
+
+fn db_rows(path: &Path) -> Vec<Row> {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM reference_data", COLUMNS.join(", "))).unwrap();
+    let rows = stmt
+        .query_map([], |row| {
+            Ok(normalize(|column| {
+                let index = COLUMNS.iter().position(|name| *name == column).unwrap();
+                row.get::<_, Option<String>>(index).unwrap()
+            }))
+        })
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<Row>>>()
+        .unwrap();
+    sorted(rows)
+}
+
+#[test]
+fn outputs_match_the_golden_file_and_each_other() {
+    let json_dir = run("json");
+    let output: Value = serde_json::from_str(&std::fs::read_to_string(json_dir.join("out.json")).unwrap()).unwrap();
+    let mut output = output.as_array().unwrap().clone();
+    for row in &mut output {
+        row.as_object_mut().unwrap().remove("updated_at");
+    }
+    let output = Value::Array(output);
+
+    let golden_path = manifest_path("tests/golden/reference_data.json");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&golden_path, serde_json::to_string_pretty(&output).unwrap() + "\n").unwrap();
+    }
+    let golden: Value = serde_json::from_str(&std::fs::read_to_string(&golden_path).unwrap()).unwrap();
+    assert_eq!(output, golden, "JSON output differs from {}", golden_path.display());
+
+    let json = json_rows(&output);
+    assert_eq!(json.len(), 11);
+    assert_eq!(db_rows(&json_dir.join("crypto_refdata.db")), json, "SQLite differs from the JSON output");
+
+    let csv_dir = run("csv");
+    let csv = csv_rows(&std::fs::read_to_string(csv_dir.join("out.csv")).unwrap());
+    assert_eq!(csv, json, "CSV output differs from the JSON output");
+
+    std::fs::remove_dir_all(json_dir).unwrap();
+    std::fs::remove_dir_all(csv_dir).unwrap();
+}